@@ -41,7 +41,7 @@ mod imp {
                 return;
             }
 
-            let window = EasierTagApplicationWindow::new(&*app);
+            let window = EasierTagApplicationWindow::new(&app);
             self.window
                 .set(window.downgrade())
                 .expect("Window already set.");
@@ -51,6 +51,13 @@ mod imp {
             window.init();
         }
 
+        fn open(&self, files: &[gio::File], _hint: &str) {
+            debug!("AdwApplication<EasierTagApplication>::open");
+            let app = self.instance();
+            app.activate();
+            app.main_window().open_files(files);
+        }
+
         fn startup(&self) {
             debug!("GtkApplication<EasierTagApplication>::startup");
             self.parent_startup();
@@ -105,6 +112,12 @@ impl EasierTagApplication {
     fn setup_accels(&self) {
         self.set_accels_for_action("app.quit", &["<Control>q"]);
         self.set_accels_for_action("window.close", &["<Control>w"]);
+        self.set_accels_for_action("win.choose-folder", &["<Control>o"]);
+        self.set_accels_for_action("win.search", &["<Control>f"]);
+        self.set_accels_for_action("win.save", &["<Control>s"]);
+        self.set_accels_for_action("win.undo", &["<Control>z"]);
+        self.set_accels_for_action("win.redo", &["<Control><Shift>z"]);
+        self.set_accels_for_action("win.rename-files", &["F2"]);
     }
 
     fn setup_css(&self) {
@@ -150,7 +163,7 @@ impl Default for EasierTagApplication {
     fn default() -> Self {
         glib::Object::new::<Self>(&[
             ("application-id", &APP_ID),
-            ("flags", &gio::ApplicationFlags::empty()),
+            ("flags", &gio::ApplicationFlags::HANDLES_OPEN),
             ("resource-base-path", &"/net/fhannenheim/EasierTag/"),
         ])
     }
@@ -0,0 +1,444 @@
+use crate::taggablefile::loader::{read_record, write_record};
+use crate::taggablefile::property_title;
+use crate::taggablefile::record::{is_valid_value, InvalidValue, Picture, PictureType, TagRecord};
+use log::debug;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const USAGE: &str = "Usage:
+  easier-tag tag show [--json] [-r] [FIELD…] PATH…
+  easier-tag tag set [-r] --FIELD VALUE… PATH…
+  easier-tag tag clear [-r] [FIELD…] PATH…
+  easier-tag tag copy [-r] [FIELD…] SOURCE PATH…
+
+Folders are replaced by the files in them, with -r also by the files in their subfolders.
+Fields limit show, clear and copy to these fields, by default all fields are used.
+clear without fields also removes all pictures and all other fields of the tag.
+
+Fields:
+  --title, --artist, --album, --album-artist, --composer, --genre, --year,
+  --track, --total-tracks, --disc, --total-discs, --cover
+
+--artist and --album-artist can be given several times. --cover takes an image file.
+An empty value removes the field.
+
+Files named like a command are opened in the window when they follow --, e.g.
+  easier-tag -- tag show";
+
+/// The commands of `easier-tag tag`
+const COMMANDS: &[&str] = &["show", "set", "clear", "copy", "help", "--help", "-h"];
+
+/// Command line options of the tag fields, with the property name of the field
+const FIELD_OPTIONS: &[(&str, &str)] = &[
+    ("--title", "title"),
+    ("--artist", "artists"),
+    ("--album", "album"),
+    ("--album-artist", "album-artists"),
+    ("--composer", "composer"),
+    ("--genre", "genre"),
+    ("--year", "year"),
+    ("--track", "track"),
+    ("--total-tracks", "total-tracks"),
+    ("--disc", "disc"),
+    ("--total-discs", "total-discs"),
+    ("--cover", "cover"),
+];
+
+#[derive(Default)]
+struct Options {
+    json: bool,
+    recursive: bool,
+    // Fields named on the command line with their value for `set`
+    fields: Vec<(&'static str, Option<String>)>,
+    paths: Vec<PathBuf>,
+}
+
+impl Options {
+    fn parse(args: &[OsString], takes_values: bool) -> Result<Self, String> {
+        let mut options = Self::default();
+        let mut args = args.iter();
+        let mut only_paths = false;
+
+        while let Some(arg) = args.next() {
+            match arg.to_str() {
+                _ if only_paths => options.paths.push(PathBuf::from(arg)),
+                Some("--") => only_paths = true,
+                Some("--json") => options.json = true,
+                Some("-r") | Some("--recursive") => options.recursive = true,
+                Some(option) if option.starts_with("--") => {
+                    let (name, inline_value) = match option.split_once('=') {
+                        Some((name, value)) => (name, Some(value.to_owned())),
+                        None => (option, None),
+                    };
+                    let property = FIELD_OPTIONS
+                        .iter()
+                        .find(|(field_option, _)| *field_option == name)
+                        .map(|(_, property)| *property)
+                        .ok_or_else(|| format!("Unknown option {}", name))?;
+
+                    let value = match (takes_values, inline_value) {
+                        (true, Some(value)) => Some(value),
+                        (true, None) => Some(
+                            args.next()
+                                .and_then(|value| value.to_str())
+                                .map(String::from)
+                                .ok_or_else(|| format!("{} needs a value", name))?,
+                        ),
+                        (false, None) => None,
+                        (false, Some(_)) => return Err(format!("{} takes no value", name)),
+                    };
+                    options.fields.push((property, value));
+                }
+                _ => options.paths.push(PathBuf::from(arg)),
+            }
+        }
+
+        if options.paths.is_empty() {
+            return Err(String::from("No files given"));
+        }
+        if takes_values && options.fields.is_empty() {
+            return Err(String::from("No fields to set given"));
+        }
+        Ok(options)
+    }
+
+    /// The fields to work on: the ones named on the command line, or all fields
+    fn properties(&self) -> Vec<&'static str> {
+        if self.fields.is_empty() {
+            return FIELD_OPTIONS
+                .iter()
+                .map(|(_, property)| *property)
+                .collect();
+        }
+        let mut properties = vec![];
+        for (property, _) in self.fields.iter() {
+            if !properties.contains(property) {
+                properties.push(*property);
+            }
+        }
+        properties
+    }
+}
+
+/// Whether the arguments after the program name are a `tag` command rather than files to
+/// open in the window. Arguments after `--` are always files.
+pub fn is_command(args: &[OsString]) -> bool {
+    match args {
+        [tag, command, ..] => {
+            tag == "tag"
+                && command
+                    .to_str()
+                    .is_some_and(|command| COMMANDS.contains(&command))
+        }
+        _ => false,
+    }
+}
+
+/// Runs `easier-tag tag <command>`, `args` being the arguments after `tag`.
+/// Returns the exit code: 1 if some files failed, 2 for invalid arguments.
+pub fn run(args: &[OsString]) -> i32 {
+    let (command, args) = match args.split_first() {
+        Some((command, args)) => (command.to_str().unwrap_or_default(), args),
+        None => {
+            eprintln!("{}", USAGE);
+            return 2;
+        }
+    };
+    if matches!(command, "help" | "--help" | "-h") {
+        println!("{}", USAGE);
+        return 0;
+    }
+
+    let options = match Options::parse(args, command == "set") {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, USAGE);
+            return 2;
+        }
+    };
+    let succeeded = match command {
+        "show" => show(&options),
+        "set" => set(&options),
+        "clear" => clear(&options),
+        "copy" => copy(&options),
+        _ => {
+            eprintln!("Unknown command {}\n\n{}", command, USAGE);
+            return 2;
+        }
+    };
+    if succeeded {
+        0
+    } else {
+        1
+    }
+}
+
+fn show(options: &Options) -> bool {
+    let (tags, succeeded) = read_all(&files(&options.paths, options.recursive));
+    let properties = options.properties();
+
+    if options.json {
+        let objects =
+            tags.iter()
+                .map(|(path, record)| {
+                    let mut members = vec![format!(
+                        "\"path\": {}",
+                        json_string(&path.to_string_lossy())
+                    )];
+                    members.extend(properties.iter().map(|property| {
+                        format!("\"{}\": {}", property, json_value(record, property))
+                    }));
+                    members.push(format!(
+                        "\"duration\": {}",
+                        record.file.duration.map_or_else(
+                            || String::from("null"),
+                            |duration| duration.as_secs_f64().to_string()
+                        )
+                    ));
+                    format!("  {{\n    {}\n  }}", members.join(",\n    "))
+                })
+                .collect::<Vec<String>>();
+        if objects.is_empty() {
+            println!("[]");
+        } else {
+            println!("[\n{}\n]", objects.join(",\n"));
+        }
+    } else {
+        for (path, record) in tags.iter() {
+            println!("{}", path.display());
+            for property in properties.iter() {
+                if let Some(text) = record.text(property) {
+                    println!("  {}: {}", property_title(property), text);
+                }
+            }
+        }
+    }
+    succeeded
+}
+
+fn set(options: &Options) -> bool {
+    // Check all values and read covers before touching any file
+    let mut cover = None;
+    for (property, value) in options.fields.iter() {
+        let value = value.as_deref().unwrap_or_default();
+        if *property == "cover" && !value.is_empty() {
+            match read_cover(Path::new(value)) {
+                Ok(picture) => cover = Some(picture),
+                Err(err) => {
+                    eprintln!("{}", err);
+                    return false;
+                }
+            }
+        } else if !is_valid_value(property, value) {
+            eprintln!("{} is not a valid {}", value, property);
+            return false;
+        }
+    }
+
+    modify_all(options, |record| {
+        // Repeated list options replace the list with all of their values
+        for (property, _) in options.fields.iter() {
+            if *property != "cover" {
+                record.clear(property);
+            }
+        }
+        for (property, value) in options.fields.iter() {
+            let value = value.as_deref().unwrap_or_default();
+            match *property {
+                "artists" => record.artists.extend(Some(value.trim().to_owned())),
+                "album-artists" => record.album_artists.extend(Some(value.trim().to_owned())),
+                // Covers the file can't store fail when writing and leave the file alone
+                "cover" => record.set_front_cover(cover.clone()),
+                _ => record.set_text(property, value)?,
+            }
+        }
+        record.artists.retain(|artist| !artist.is_empty());
+        record.album_artists.retain(|artist| !artist.is_empty());
+        Ok(())
+    })
+}
+
+fn clear(options: &Options) -> bool {
+    let mut properties = options.properties();
+    // Without fields the whole tag goes, not only the fields that have an option
+    if options.fields.is_empty() {
+        properties.extend(["pictures", "extra"]);
+    }
+    modify_all(options, |record| {
+        for property in properties.iter() {
+            record.clear(property);
+        }
+        Ok(())
+    })
+}
+
+fn copy(options: &Options) -> bool {
+    let (source, targets) = match options.paths.split_first() {
+        Some((source, targets)) if !targets.is_empty() => (source, targets),
+        _ => {
+            eprintln!(
+                "Copying needs a source file and at least one target\n\n{}",
+                USAGE
+            );
+            return false;
+        }
+    };
+    let source = match read_record(source) {
+        Ok(source) => source,
+        Err(err) => {
+            eprintln!("{}: {}", source.display(), err);
+            return false;
+        }
+    };
+
+    let properties = options.properties();
+    let target_options = Options {
+        paths: targets.to_vec(),
+        recursive: options.recursive,
+        ..Options::default()
+    };
+    modify_all(&target_options, |record| {
+        for property in properties.iter() {
+            record.copy_field(&source, property);
+        }
+        Ok(())
+    })
+}
+
+/// Applies `modify` to the tags of all files of `options` and writes them. Files for which
+/// `modify` fails are left alone.
+fn modify_all<F: Fn(&mut TagRecord) -> Result<(), InvalidValue>>(
+    options: &Options,
+    modify: F,
+) -> bool {
+    let (tags, mut succeeded) = read_all(&files(&options.paths, options.recursive));
+    for (path, saved) in tags {
+        let mut record = saved.clone();
+        if let Err(err) = modify(&mut record) {
+            eprintln!("{}: {}", path.display(), err);
+            succeeded = false;
+            continue;
+        }
+        if let Err(err) = write_record(&path, &record, &saved) {
+            eprintln!("{}: {}", path.display(), err);
+            succeeded = false;
+        }
+    }
+    succeeded
+}
+
+/// Returns the files at `paths`, replacing folders with the files in them. The flag is set
+/// for files that were named explicitly rather than found in a folder.
+fn files(paths: &[PathBuf], recursive: bool) -> Vec<(PathBuf, bool)> {
+    let mut files = vec![];
+    for path in paths {
+        if path.is_dir() {
+            files.extend(
+                folder_files(path, recursive)
+                    .into_iter()
+                    .map(|path| (path, false)),
+            );
+        } else {
+            files.push((path.clone(), true));
+        }
+    }
+    files
+}
+
+fn folder_files(folder: &Path, recursive: bool) -> Vec<PathBuf> {
+    let mut entries = match fs::read_dir(folder) {
+        Ok(entries) => entries.filter_map(|entry| entry.ok()).collect::<Vec<_>>(),
+        Err(err) => {
+            eprintln!("{}: {}", folder.display(), err);
+            return vec![];
+        }
+    };
+    entries.sort_by_key(|entry| entry.file_name());
+
+    let mut files = vec![];
+    for entry in entries {
+        // Symbolic links to folders are not followed, so they can't cause an endless scan
+        match entry.file_type() {
+            Ok(file_type) if file_type.is_dir() => {
+                if recursive {
+                    files.extend(folder_files(&entry.path(), recursive));
+                }
+            }
+            Ok(_) => files.push(entry.path()),
+            Err(err) => eprintln!("{}: {}", entry.path().display(), err),
+        }
+    }
+    files
+}
+
+/// Reads the tags of `files`. Files found in folders that have no readable tag are
+/// skipped, the others are reported and make the result unsuccessful.
+fn read_all(files: &[(PathBuf, bool)]) -> (Vec<(PathBuf, TagRecord)>, bool) {
+    let mut tags = vec![];
+    let mut succeeded = true;
+    for (path, explicit) in files {
+        match read_record(path) {
+            Ok(record) => tags.push((path.clone(), record)),
+            Err(err) if *explicit => {
+                eprintln!("{}: {}", path.display(), err);
+                succeeded = false;
+            }
+            Err(err) => debug!("Skipping {}, {}", path.display(), err),
+        }
+    }
+    (tags, succeeded)
+}
+
+fn read_cover(path: &Path) -> Result<Picture, String> {
+    // The image format is recognized by the contents, like in the cover panel
+    let data = fs::read(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+    Picture::from_image_data(PictureType::FrontCover, data)
+        .ok_or_else(|| format!("{}: unsupported cover image type", path.display()))
+}
+
+fn json_value(record: &TagRecord, property: &str) -> String {
+    let list = |list: &Vec<String>| {
+        let entries = list
+            .iter()
+            .map(|entry| json_string(entry))
+            .collect::<Vec<String>>();
+        format!("[{}]", entries.join(", "))
+    };
+    match property {
+        "artists" => list(&record.artists),
+        "album-artists" => list(&record.album_artists),
+        "year" | "track" | "total-tracks" | "disc" | "total-discs" => record
+            .text(property)
+            .unwrap_or_else(|| String::from("null")),
+        "cover" => match record.front_cover() {
+            Some(cover) => format!(
+                "{{\"mime-type\": {}, \"size\": {}}}",
+                json_string(&cover.mime_type),
+                cover.data.len()
+            ),
+            None => String::from("null"),
+        },
+        _ => record
+            .text(property)
+            .map_or_else(|| String::from("null"), |text| json_string(&text)),
+    }
+}
+
+fn json_string(text: &str) -> String {
+    let mut json = String::with_capacity(text.len() + 2);
+    json.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if (c as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
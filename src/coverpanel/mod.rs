@@ -0,0 +1,538 @@
+use crate::taggablefile::record::{Picture, PictureType};
+use crate::taggablefile::{picture_type_title, TaggableFile};
+use gettextrs::gettext;
+use gtk::{
+    gdk, gio, glib, glib::clone, glib::SignalHandlerId, prelude::*, subclass::prelude::*, Button,
+    CompositeTemplate, Entry, FileChooserNative, Label, ListBox, Widget,
+};
+use std::cell::{Cell, RefCell};
+
+mod imp {
+    use super::*;
+
+    #[derive(CompositeTemplate, Default)]
+    #[template(resource = "/net/fhannenheim/EasierTag/ui/coverpanel.ui")]
+    pub struct CoverPanel {
+        #[template_child]
+        pub picture: TemplateChild<gtk::Picture>,
+        #[template_child]
+        pub placeholder_label: TemplateChild<Label>,
+        #[template_child]
+        pub info_label: TemplateChild<Label>,
+        #[template_child]
+        pub replace_button: TemplateChild<Button>,
+        #[template_child]
+        pub paste_button: TemplateChild<Button>,
+        #[template_child]
+        pub export_button: TemplateChild<Button>,
+        #[template_child]
+        pub remove_button: TemplateChild<Button>,
+        #[template_child]
+        pub pictures_label: TemplateChild<Label>,
+        #[template_child]
+        pub pictures_listbox: TemplateChild<ListBox>,
+        #[template_child]
+        pub add_picture_button: TemplateChild<Button>,
+
+        pub files: RefCell<Vec<TaggableFile>>,
+        pub notify_handlers: RefCell<Vec<(TaggableFile, SignalHandlerId)>>,
+        // Kept alive while it is shown
+        pub file_chooser: RefCell<Option<FileChooserNative>>,
+        // Set while a picture is edited in its row so that the rows are not rebuilt
+        pub updating: Cell<bool>,
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for CoverPanel {
+        // `NAME` needs to match `class` attribute of template
+        const NAME: &'static str = "CoverPanel";
+        type Type = super::CoverPanel;
+        type ParentType = Widget;
+
+        fn class_init(klass: &mut Self::Class) {
+            klass.bind_template();
+        }
+
+        fn instance_init(obj: &glib::subclass::InitializingObject<Self>) {
+            obj.init_template();
+        }
+    }
+
+    impl ObjectImpl for CoverPanel {
+        fn constructed(&self) {
+            self.parent_constructed();
+            let obj = self.obj();
+
+            self.replace_button
+                .connect_clicked(clone!(@weak obj => move |_| {
+                    obj.choose_image(&gettext("Replace Cover"), |panel, cover| {
+                        panel.set_cover(Some(cover));
+                    });
+                }));
+            self.paste_button
+                .connect_clicked(clone!(@weak obj => move |_| obj.paste()));
+            self.export_button
+                .connect_clicked(clone!(@weak obj => move |_| obj.choose_export_file()));
+            self.remove_button
+                .connect_clicked(clone!(@weak obj => move |_| obj.set_cover(None)));
+            self.add_picture_button
+                .connect_clicked(clone!(@weak obj => move |_| {
+                    obj.choose_image(&gettext("Add Picture"), |panel, picture| {
+                        panel.add_picture(picture);
+                    });
+                }));
+
+            // Image files from file managers and images from other applications
+            let drop_target = gtk::DropTarget::new(glib::Type::INVALID, gdk::DragAction::COPY);
+            drop_target.set_types(&[gdk::FileList::static_type(), gdk::Texture::static_type()]);
+            drop_target.connect_drop(
+                clone!(@weak obj => @default-return false, move |_, value, _, _| {
+                    let files = obj.imp().files.borrow().clone();
+                    if files.is_empty() || !files.iter().all(TaggableFile::is_editable) {
+                        return false;
+                    }
+                    if let Ok(texture) = value.get::<gdk::Texture>() {
+                        obj.set_cover_from_texture(&texture);
+                        return true;
+                    }
+                    match value.get::<gdk::FileList>().map(|file_list| file_list.files()) {
+                        Ok(files) if !files.is_empty() => {
+                            obj.load_image(&files[0], |panel, cover| panel.set_cover(Some(cover)));
+                            true
+                        }
+                        _ => false,
+                    }
+                }),
+            );
+            obj.add_controller(&drop_target);
+
+            obj.refresh();
+        }
+        fn dispose(&self) {
+            self.obj().disconnect_files();
+            while let Some(child) = self.obj().first_child() {
+                child.unparent();
+            }
+        }
+    }
+
+    impl WidgetImpl for CoverPanel {}
+}
+
+glib::wrapper! {
+    pub struct CoverPanel(ObjectSubclass<imp::CoverPanel>)
+        @extends Widget;
+}
+
+impl CoverPanel {
+    pub fn new() -> Self {
+        glib::Object::builder().build()
+    }
+
+    /// Shows the cover of `files`, replacing the previous selection
+    pub fn set_files(&self, files: Vec<TaggableFile>) {
+        let imp = self.imp();
+        self.disconnect_files();
+
+        let handlers = files
+            .iter()
+            .map(|taggable_file| {
+                let handler = taggable_file.connect_notify_local(
+                    Some("pictures"),
+                    clone!(@weak self as panel => move |_, _| panel.refresh()),
+                );
+                (taggable_file.clone(), handler)
+            })
+            .collect();
+        imp.notify_handlers.replace(handlers);
+        imp.files.replace(files);
+
+        self.refresh();
+    }
+
+    /// Replaces the front cover of all selected files, `None` removes it
+    pub fn set_cover(&self, cover: Option<Picture>) {
+        let files = self.imp().files.borrow().clone();
+        // The cover of all files is replaced in one undo step
+        let set_covers = || {
+            for taggable_file in files.iter() {
+                taggable_file.set_front_cover(cover.clone());
+            }
+        };
+        match files
+            .first()
+            .and_then(|taggable_file| taggable_file.history())
+        {
+            Some(history) => history.group(set_covers),
+            None => set_covers(),
+        }
+    }
+
+    /// Adds `picture` to all selected files, as front cover to files without one
+    fn add_picture(&self, picture: Picture) {
+        self.edit_pictures(|pictures| {
+            let mut picture = picture.clone();
+            picture.picture_type = if pictures
+                .iter()
+                .any(|picture| picture.picture_type == PictureType::FrontCover)
+            {
+                PictureType::Other
+            } else {
+                PictureType::FrontCover
+            };
+            pictures.push(picture);
+        });
+        self.refresh_pictures();
+    }
+
+    /// Changes the pictures of each selected file with `edit`, in one undo step
+    fn edit_pictures<F: Fn(&mut Vec<Picture>)>(&self, edit: F) {
+        let imp = self.imp();
+        let files = imp.files.borrow().clone();
+        let edit_files = || {
+            for taggable_file in files.iter() {
+                let mut pictures = taggable_file.pictures();
+                let old_pictures = pictures.clone();
+                edit(&mut pictures);
+                if pictures != old_pictures {
+                    taggable_file.set_pictures(pictures);
+                }
+            }
+        };
+
+        imp.updating.set(true);
+        match files
+            .first()
+            .and_then(|taggable_file| taggable_file.history())
+        {
+            Some(history) => history.group(edit_files),
+            None => edit_files(),
+        }
+        imp.updating.set(false);
+    }
+
+    /// The pictures all selected files share, `None` if they differ
+    fn shared_pictures(&self) -> Option<Vec<Picture>> {
+        let files = self.imp().files.borrow();
+        let (first, rest) = files.split_first()?;
+        let first = first.borrow_record();
+        rest.iter()
+            .all(|taggable_file| taggable_file.borrow_record().same_field(&first, "pictures"))
+            .then(|| first.pictures.clone())
+    }
+
+    /// The front cover all selected files share, `None` if they have none or different ones
+    fn shared_cover(&self) -> Option<Picture> {
+        let files = self.imp().files.borrow();
+        let (first, rest) = files.split_first()?;
+        let first = first.borrow_record();
+        if rest
+            .iter()
+            .all(|taggable_file| taggable_file.borrow_record().same_field(&first, "cover"))
+        {
+            first.front_cover().cloned()
+        } else {
+            None
+        }
+    }
+
+    fn refresh(&self) {
+        let imp = self.imp();
+        let files = imp.files.borrow().clone();
+        let cover = self.shared_cover();
+        let texture = cover
+            .as_ref()
+            .and_then(|_| files.first())
+            .and_then(|taggable_file| taggable_file.cover());
+
+        imp.picture.set_paintable(texture.as_ref());
+        imp.picture.set_visible(texture.is_some());
+        imp.placeholder_label.set_visible(texture.is_none());
+        let has_cover =
+            |taggable_file: &TaggableFile| taggable_file.borrow_record().front_cover().is_some();
+        if cover.is_none() && files.iter().any(has_cover) {
+            imp.placeholder_label
+                .set_label(&gettext("Different Covers"));
+        } else if cover.is_some() {
+            imp.placeholder_label
+                .set_label(&gettext("Cover Can't Be Shown"));
+        } else {
+            imp.placeholder_label.set_label(&gettext("No Cover"));
+        }
+
+        let info = match (&cover, &texture) {
+            (Some(cover), Some(texture)) => format!(
+                "{} × {}, {}",
+                texture.width(),
+                texture.height(),
+                cover.mime_type
+            ),
+            (Some(cover), None) => cover.mime_type.clone(),
+            _ => String::new(),
+        };
+        imp.info_label.set_label(&info);
+
+        let editable = !files.is_empty() && files.iter().all(TaggableFile::is_editable);
+        imp.replace_button.set_sensitive(editable);
+        imp.paste_button.set_sensitive(editable);
+        imp.export_button.set_sensitive(cover.is_some());
+        imp.remove_button
+            .set_sensitive(editable && files.iter().any(has_cover));
+        imp.add_picture_button.set_sensitive(editable);
+
+        if !imp.updating.get() {
+            self.refresh_pictures();
+        }
+    }
+
+    /// Shows a row for every picture if the selected files share their pictures
+    fn refresh_pictures(&self) {
+        let imp = self.imp();
+        while let Some(row) = imp.pictures_listbox.first_child() {
+            imp.pictures_listbox.remove(&row);
+        }
+
+        let pictures = self.shared_pictures();
+        imp.pictures_label
+            .set_visible(pictures.is_none() && !imp.files.borrow().is_empty());
+        let pictures = pictures.unwrap_or_default();
+        imp.pictures_listbox.set_visible(!pictures.is_empty());
+        for (index, picture) in pictures.iter().enumerate() {
+            imp.pictures_listbox
+                .append(&self.picture_row(index, picture));
+        }
+    }
+
+    /// Creates a row to change the type and description of the picture at `index`
+    fn picture_row(&self, index: usize, picture: &Picture) -> gtk::Box {
+        let row = gtk::Box::new(gtk::Orientation::Horizontal, 6);
+        row.set_margin_top(6);
+        row.set_margin_bottom(6);
+        row.set_margin_start(6);
+        row.set_margin_end(6);
+
+        let thumbnail = gtk::Picture::new();
+        thumbnail.set_size_request(48, 48);
+        thumbnail.set_can_shrink(true);
+        match gdk::Texture::from_bytes(&glib::Bytes::from(&picture.data)) {
+            Ok(texture) => thumbnail.set_paintable(Some(&texture)),
+            Err(_) => thumbnail.set_tooltip_text(Some(picture.mime_type.as_str())),
+        }
+        row.append(&thumbnail);
+
+        let fields = gtk::Box::new(gtk::Orientation::Vertical, 6);
+        fields.set_hexpand(true);
+        let titles = PictureType::ALL
+            .iter()
+            .map(|picture_type| picture_type_title(*picture_type))
+            .collect::<Vec<String>>();
+        let titles = titles.iter().map(String::as_str).collect::<Vec<&str>>();
+        let type_dropdown = gtk::DropDown::from_strings(&titles);
+        type_dropdown.set_selected(u32::from(picture.picture_type.number()));
+        type_dropdown.connect_selected_notify(clone!(@weak self as panel => move |dropdown| {
+            let picture_type = PictureType::from_number(dropdown.selected() as u8);
+            panel.edit_pictures(|pictures| {
+                if let Some(picture) = pictures.get_mut(index) {
+                    picture.picture_type = picture_type;
+                }
+            });
+        }));
+        fields.append(&type_dropdown);
+
+        let description_entry = Entry::new();
+        description_entry.set_placeholder_text(Some(gettext("Description").as_str()));
+        description_entry.set_text(&picture.description);
+        let apply_description = clone!(@weak self as panel => move |entry: &Entry| {
+            let description = entry.text().to_string();
+            panel.edit_pictures(|pictures| {
+                if let Some(picture) = pictures.get_mut(index) {
+                    picture.description = description.clone();
+                }
+            });
+        });
+        description_entry.connect_activate(apply_description.clone());
+        let focus_controller = gtk::EventControllerFocus::new();
+        focus_controller.connect_leave(clone!(@weak description_entry => move |_| {
+            apply_description(&description_entry);
+        }));
+        description_entry.add_controller(&focus_controller);
+        fields.append(&description_entry);
+        row.append(&fields);
+
+        let remove_button = Button::from_icon_name("list-remove-symbolic");
+        remove_button.set_valign(gtk::Align::Center);
+        remove_button.set_tooltip_text(Some(gettext("Remove Picture").as_str()));
+        remove_button.add_css_class("flat");
+        remove_button.connect_clicked(clone!(@weak self as panel => move |_| {
+            panel.edit_pictures(|pictures| {
+                if index < pictures.len() {
+                    pictures.remove(index);
+                }
+            });
+            panel.refresh_pictures();
+        }));
+        row.append(&remove_button);
+        row
+    }
+
+    /// Lets the user choose an image file and passes it to `on_image` as front cover
+    fn choose_image<F: Fn(&Self, Picture) + Clone + 'static>(&self, title: &str, on_image: F) {
+        let window = self
+            .root()
+            .and_then(|root| root.downcast::<gtk::Window>().ok());
+        let file_chooser = FileChooserNative::new(
+            Some(title),
+            window.as_ref(),
+            gtk::FileChooserAction::Open,
+            Some(gettext("_Open").as_str()),
+            Some(gettext("_Cancel").as_str()),
+        );
+        file_chooser.set_modal(true);
+        let filter = gtk::FileFilter::new();
+        filter.set_name(Some(gettext("Images").as_str()));
+        filter.add_mime_type("image/*");
+        file_chooser.add_filter(&filter);
+
+        file_chooser.connect_response(
+            clone!(@weak self as panel => move |file_chooser, response| {
+                if response == gtk::ResponseType::Accept {
+                    if let Some(file) = file_chooser.file() {
+                        panel.load_image(&file, on_image.clone());
+                    }
+                }
+                panel.imp().file_chooser.replace(None);
+            }),
+        );
+        file_chooser.show();
+        self.imp().file_chooser.replace(Some(file_chooser));
+    }
+
+    /// Reads the image in `file` and passes it to `on_image` as front cover
+    fn load_image<F: FnOnce(&Self, Picture) + 'static>(&self, file: &gio::File, on_image: F) {
+        let name = file.parse_name();
+        file.load_contents_async(
+            gio::Cancellable::NONE,
+            clone!(@weak self as panel => move |result| {
+                match result {
+                    Ok((data, _)) => match Picture::from_image_data(PictureType::FrontCover, data) {
+                        Some(picture) => on_image(&panel, picture),
+                        None => panel.show_error(
+                            &gettext("The file is not an image"),
+                            &gettext("Pictures can be JPEG, PNG, GIF, BMP, TIFF or WebP images."),
+                        ),
+                    },
+                    Err(err) => {
+                        log::warn!("Failed to read {}, {}", name, err);
+                        panel.show_error(&gettext("The image could not be read"), err.message());
+                    }
+                }
+            }),
+        );
+    }
+
+    fn paste(&self) {
+        self.clipboard().read_texture_async(
+            gio::Cancellable::NONE,
+            clone!(@weak self as panel => move |result| {
+                match result {
+                    Ok(Some(texture)) => panel.set_cover_from_texture(&texture),
+                    Ok(None) => (),
+                    Err(err) => {
+                        log::debug!("No image to paste, {}", err);
+                        panel.show_error(&gettext("The clipboard contains no image"), "");
+                    }
+                }
+            }),
+        );
+    }
+
+    /// Images that only exist decoded, e.g. when pasted, are stored as PNG
+    fn set_cover_from_texture(&self, texture: &gdk::Texture) {
+        let data = texture.save_to_png_bytes().to_vec();
+        if let Some(cover) = Picture::from_image_data(PictureType::FrontCover, data) {
+            self.set_cover(Some(cover));
+        }
+    }
+
+    fn choose_export_file(&self) {
+        let cover = match self.shared_cover() {
+            Some(cover) => cover,
+            None => return,
+        };
+        let window = self
+            .root()
+            .and_then(|root| root.downcast::<gtk::Window>().ok());
+        let file_chooser = FileChooserNative::new(
+            Some(gettext("Export Cover").as_str()),
+            window.as_ref(),
+            gtk::FileChooserAction::Save,
+            Some(gettext("_Export").as_str()),
+            Some(gettext("_Cancel").as_str()),
+        );
+        file_chooser.set_modal(true);
+        let extension = cover
+            .mime_type
+            .strip_prefix("image/")
+            .map(|subtype| subtype.replace("jpeg", "jpg"))
+            .unwrap_or_else(|| String::from("img"));
+        file_chooser.set_current_name(&format!("cover.{}", extension));
+
+        file_chooser.connect_response(
+            clone!(@weak self as panel => move |file_chooser, response| {
+                if response == gtk::ResponseType::Accept {
+                    if let Some(file) = file_chooser.file() {
+                        panel.export(&file, cover.data.clone());
+                    }
+                }
+                panel.imp().file_chooser.replace(None);
+            }),
+        );
+        file_chooser.show();
+        self.imp().file_chooser.replace(Some(file_chooser));
+    }
+
+    fn export(&self, file: &gio::File, data: Vec<u8>) {
+        let name = file.parse_name();
+        file.replace_contents_async(
+            data,
+            None,
+            false,
+            gio::FileCreateFlags::REPLACE_DESTINATION,
+            gio::Cancellable::NONE,
+            clone!(@weak self as panel => move |result| {
+                if let Err((_, err)) = result {
+                    log::warn!("Failed to export the cover to {}, {}", name, err);
+                    panel.show_error(&gettext("The cover could not be exported"), err.message());
+                }
+            }),
+        );
+    }
+
+    fn show_error(&self, text: &str, details: &str) {
+        let window = self
+            .root()
+            .and_then(|root| root.downcast::<gtk::Window>().ok());
+        let dialog = gtk::MessageDialog::builder()
+            .modal(true)
+            .message_type(gtk::MessageType::Error)
+            .buttons(gtk::ButtonsType::Close)
+            .text(text)
+            .secondary_text(details)
+            .build();
+        dialog.set_transient_for(window.as_ref());
+        dialog.connect_response(|dialog, _| dialog.close());
+        dialog.present();
+    }
+
+    fn disconnect_files(&self) {
+        for (taggable_file, handler) in self.imp().notify_handlers.take() {
+            taggable_file.disconnect(handler);
+        }
+    }
+}
+
+impl Default for CoverPanel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,39 @@
+/// A column the file list can show
+#[derive(Clone, Copy, Debug)]
+pub struct ColumnInfo {
+    /// The TaggableFile property shown in the column, also used to save the layout
+    pub id: &'static str,
+    pub editable: bool,
+}
+
+const fn column(id: &'static str, editable: bool) -> ColumnInfo {
+    ColumnInfo { id, editable }
+}
+
+/// All columns in the order they are offered in the column header menu. Which of them
+/// are shown is saved in the `columns` setting.
+pub const COLUMNS: &[ColumnInfo] = &[
+    // Files are renamed from their tags, not by editing the name
+    column("filename", false),
+    column("title", true),
+    column("artists", true),
+    column("album", true),
+    column("album-artists", true),
+    column("composer", true),
+    column("year", true),
+    column("disc", true),
+    column("total-discs", true),
+    column("track", true),
+    column("total-tracks", true),
+    column("genre", true),
+    column("duration", false),
+    column("format", false),
+    column("codec", false),
+    column("bitrate", false),
+    column("sample-rate", false),
+    column("bit-depth", false),
+    column("channels", false),
+    column("tag-format", false),
+    column("file-size", false),
+    column("path", false),
+];
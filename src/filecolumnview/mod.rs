@@ -1,10 +1,24 @@
+use crate::config::APP_ID;
+use crate::filecolumnview::columns::{ColumnInfo, COLUMNS};
+use crate::filecolumnview::tagcell::TagCell;
+use crate::taggablefile::query::Query;
+use crate::taggablefile::sorting::{compare_field, natural_cmp};
 use crate::taggablefile::taggablefilelist::TaggableFileListModel;
-use crate::taggablefile::TaggableFile;
+use crate::taggablefile::{property_title, TaggableFile};
+use gettextrs::gettext;
 use gtk::{
-    gio, glib, glib::closure, prelude::*, subclass::prelude::*, ColumnView, ColumnViewColumn,
-    CompositeTemplate, ConstantExpression, Label, ListItem, MultiSelection, PropertyExpression,
-    SignalListItemFactory, Widget,
+    gio, glib, glib::clone, glib::closure, prelude::*, subclass::prelude::*, ColumnView,
+    ColumnViewColumn, CompositeTemplate, ConstantExpression, CustomFilter, CustomSorter,
+    FilterChange, FilterListModel, Image, ListItem, MultiSelection, ProgressBar,
+    PropertyExpression, SearchBar, SearchEntry, SignalListItemFactory, SortListModel, SorterChange,
+    ToggleButton, Widget,
 };
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+mod columns;
+mod tagcell;
 
 mod imp {
     use super::*;
@@ -13,23 +27,25 @@ mod imp {
     #[template(resource = "/net/fhannenheim/EasierTag/ui/filecolumnview.ui")]
     pub struct FileColumnView {
         #[template_child]
-        pub file_column_view: TemplateChild<ColumnView>,
-        #[template_child]
-        pub filename_column: TemplateChild<ColumnViewColumn>,
-        #[template_child]
-        pub title_column: TemplateChild<ColumnViewColumn>,
-        #[template_child]
-        pub artist_column: TemplateChild<ColumnViewColumn>,
+        pub search_bar: TemplateChild<SearchBar>,
         #[template_child]
-        pub album_column: TemplateChild<ColumnViewColumn>,
+        pub search_entry: TemplateChild<SearchEntry>,
         #[template_child]
-        pub year_column: TemplateChild<ColumnViewColumn>,
+        pub problems_button: TemplateChild<ToggleButton>,
         #[template_child]
-        pub disc_column: TemplateChild<ColumnViewColumn>,
+        pub file_column_view: TemplateChild<ColumnView>,
         #[template_child]
-        pub track_column: TemplateChild<ColumnViewColumn>,
+        pub progress_bar: TemplateChild<ProgressBar>,
         #[template_child]
-        pub genre_column: TemplateChild<ColumnViewColumn>,
+        pub modified_column: TemplateChild<ColumnViewColumn>,
+        // All columns the file list can show by their field id, hidden ones included
+        pub columns: RefCell<Vec<(&'static str, ColumnViewColumn)>>,
+
+        // Properties of the columns the files are sorted by, most significant first, and
+        // whether they are sorted descending
+        pub sort_keys: RefCell<Vec<(String, bool)>>,
+
+        pub query: RefCell<Query>,
     }
 
     #[glib::object_subclass]
@@ -74,66 +90,419 @@ impl FileColumnView {
     pub fn column_view(&self) -> ColumnView {
         self.imp().file_column_view.clone()
     }
-    pub fn columns(&self) -> Vec<ColumnViewColumn> {
-        vec![
-            self.imp().filename_column.clone(),
-            self.imp().title_column.clone(),
-            self.imp().artist_column.clone(),
-            self.imp().album_column.clone(),
-            self.imp().year_column.clone(),
-            self.imp().disc_column.clone(),
-            self.imp().track_column.clone(),
-            self.imp().genre_column.clone(),
-        ]
+    pub fn selection_model(&self) -> MultiSelection {
+        self.column_view()
+            .model()
+            .and_then(|model| model.downcast::<MultiSelection>().ok())
+            .expect("FileColumnView model is not a MultiSelection")
+    }
+    pub fn sort_model(&self) -> SortListModel {
+        self.selection_model()
+            .model()
+            .and_then(|model| model.downcast::<SortListModel>().ok())
+            .expect("FileColumnView model is not a SortListModel")
+    }
+    pub fn filter_model(&self) -> FilterListModel {
+        self.sort_model()
+            .model()
+            .and_then(|model| model.downcast::<FilterListModel>().ok())
+            .expect("FileColumnView model is not a FilterListModel")
+    }
+    pub fn file_list(&self) -> TaggableFileListModel {
+        self.filter_model()
+            .model()
+            .and_then(|model| model.downcast::<TaggableFileListModel>().ok())
+            .expect("FileColumnView model is not a TaggableFileListModel")
+    }
+    pub fn selected_files(&self) -> Vec<TaggableFile> {
+        let selection_model = self.selection_model();
+        let selection = selection_model.selection();
+        (0..selection.size() as u32)
+            .filter_map(|index| selection_model.item(selection.nth(index)))
+            .filter_map(|item| item.downcast::<TaggableFile>().ok())
+            .collect()
+    }
+    /// Returns the files the search and the filters let through, in the order they are shown
+    pub fn shown_files(&self) -> Vec<TaggableFile> {
+        let selection_model = self.selection_model();
+        (0..selection_model.n_items())
+            .filter_map(|position| selection_model.item(position))
+            .filter_map(|item| item.downcast::<TaggableFile>().ok())
+            .collect()
+    }
+    /// Returns the columns that are shown, in the order they are shown
+    fn visible_columns(&self) -> Vec<(&'static str, ColumnViewColumn)> {
+        let columns = self.imp().columns.borrow();
+        let view_columns = self.column_view().columns();
+        (0..view_columns.n_items())
+            .filter_map(|position| view_columns.item(position))
+            .filter_map(|item| item.downcast::<ColumnViewColumn>().ok())
+            .filter(|column| column.is_visible())
+            .filter_map(|column| columns.iter().find(|(_, c)| *c == column).cloned())
+            .collect()
     }
     pub fn init(&self) {
-        for column in self.columns() {
-            let column_list_factory = SignalListItemFactory::new();
-
-            let column_title = column.title().unwrap_or(String::from("").into());
-            column_list_factory.connect_setup(move |_, list_item| {
-                let label = Label::new(Some(""));
-                list_item.set_child(Some(&label));
-
-                let list_item_expr = ConstantExpression::new(list_item);
-                let taggablefile_expr =
-                    PropertyExpression::new(ListItem::static_type(), Some(&list_item_expr), "item");
-
-                let _column_title = column_title.clone();
-                let label_expr =
-                    taggablefile_expr.chain_closure::<String>(closure!(|_: Option<
-                        glib::Object,
-                    >,
-                                                                        taggable_file: Option<
-                        glib::Object,
-                    >| {
-                        if let Some(taggable_file) = taggable_file {
-                            if let Ok(taggable_file) = taggable_file.downcast::<TaggableFile>() {
-                                return match _column_title.as_str() {
-                                    "Filename" => taggable_file.property::<String>("filename"),
-                                    "Title" => taggable_file.property::<String>("title"),
-                                    "Artist" => {
-                                        taggable_file.property::<Vec<String>>("artists").join(", ")
-                                    }
-                                    "Album" => taggable_file.property::<String>("album"),
-                                    "Year" => taggable_file.property::<String>("year"),
-                                    "Disc" => taggable_file.property::<String>("disc"),
-                                    "Track" => taggable_file.property::<String>("track"),
-                                    "Genre" => taggable_file.property::<String>("genre"),
-                                    &_ => String::new(),
-                                };
-                            }
-                        }
-                        String::new()
-                    }));
-                label_expr.bind(&label, "label", Widget::NONE);
-            });
-            column.set_factory(Some(&column_list_factory));
-        }
+        let modified_list_factory = SignalListItemFactory::new();
+        modified_list_factory.connect_setup(move |_, list_item| {
+            let status = gtk::Box::new(gtk::Orientation::Horizontal, 0);
+            list_item.set_child(Some(&status));
+
+            let list_item_expr = ConstantExpression::new(list_item);
+            let taggablefile_expr =
+                PropertyExpression::new(ListItem::static_type(), Some(&list_item_expr), "item");
+            for (property, icon_name, tooltip) in [
+                (
+                    "modified",
+                    "document-edit-symbolic",
+                    gettext("Unsaved changes"),
+                ),
+                (
+                    "unsupported",
+                    "action-unavailable-symbolic",
+                    gettext("Tags of this file format can't be edited"),
+                ),
+            ] {
+                let image = Image::from_icon_name(icon_name);
+                image.set_tooltip_text(Some(tooltip.as_str()));
+                status.append(&image);
+
+                let property_expr = PropertyExpression::new(
+                    TaggableFile::static_type(),
+                    Some(&taggablefile_expr),
+                    property,
+                );
+                property_expr.bind(&image, "visible", Widget::NONE);
+            }
+
+            // Files whose tag could not be read show why in the tooltip
+            let error_image = Image::from_icon_name("dialog-error-symbolic");
+            error_image.add_css_class("error");
+            status.append(&error_image);
+            let error_expr = PropertyExpression::new(
+                TaggableFile::static_type(),
+                Some(&taggablefile_expr),
+                "error",
+            );
+            error_expr.bind(&error_image, "tooltip-text", Widget::NONE);
+            error_expr
+                .chain_closure::<bool>(closure!(
+                    |_: Option<glib::Object>, error: Option<String>| { error.is_some() }
+                ))
+                .bind(&error_image, "visible", Widget::NONE);
+        });
+        self.imp()
+            .modified_column
+            .set_factory(Some(&modified_list_factory));
+
+        self.restore_columns();
         let model = TaggableFileListModel::new();
 
-        let multi_selection = MultiSelection::new(Some(&model));
+        // Show how far reading the tags of the selected folders got
+        model
+            .bind_property("loading", &self.imp().progress_bar.get(), "visible")
+            .flags(glib::BindingFlags::SYNC_CREATE)
+            .build();
+        model
+            .bind_property("progress", &self.imp().progress_bar.get(), "fraction")
+            .flags(glib::BindingFlags::SYNC_CREATE)
+            .build();
+
+        let file_sorter = CustomSorter::new(
+            clone!(@weak self as view => @default-return gtk::Ordering::Equal, move |a, b| {
+                for (property, descending) in view.imp().sort_keys.borrow().iter() {
+                    let ordering = compare_files(a, b, property);
+                    let ordering = if *descending { ordering.reverse() } else { ordering };
+                    if ordering != Ordering::Equal {
+                        return ordering.into();
+                    }
+                }
+                Ordering::Equal.into()
+            }),
+        );
+        let file_filter = CustomFilter::new(
+            clone!(@weak self as view => @default-return true, move |item| {
+                let imp = view.imp();
+                let taggable_file = match item.downcast_ref::<TaggableFile>() {
+                    Some(taggable_file) => taggable_file,
+                    None => return false,
+                };
+                // The problems filter only shows files whose tag can't be read or edited
+                if imp.problems_button.is_active() && taggable_file.is_editable() {
+                    return false;
+                }
+                let query = imp.query.borrow();
+                query.is_empty()
+                    || query.matches(&taggable_file.borrow_record(), &taggable_file.property_text("filename"))
+            }),
+        );
+        let filter_model = FilterListModel::new(Some(&model), Some(&file_filter));
+        let sort_model = SortListModel::new(Some(&filter_model), Some(&file_sorter));
+        let multi_selection = MultiSelection::new(Some(&sort_model));
         self.column_view().set_model(Some(&multi_selection));
+
+        let imp = self.imp();
+        imp.search_bar.connect_entry(&imp.search_entry.get());
+        imp.search_entry
+            .connect_search_changed(clone!(@weak self as view => move |entry| {
+                view.imp().query.replace(Query::parse(&entry.text()));
+                if let Some(filter) = view.filter_model().filter() {
+                    filter.changed(FilterChange::Different);
+                }
+            }));
+        imp.problems_button
+            .connect_toggled(clone!(@weak self as view => move |_| {
+                if let Some(filter) = view.filter_model().filter() {
+                    filter.changed(FilterChange::Different);
+                }
+            }));
+        // Closing the search bar shows all files again
+        imp.search_bar.connect_search_mode_enabled_notify(
+            clone!(@weak self as view => move |search_bar| {
+                if !search_bar.is_search_mode() {
+                    view.imp().search_entry.set_text("");
+                    view.imp().problems_button.set_active(false);
+                }
+            }),
+        );
+
+        self.restore_sort_order();
+        // Dragging a column header to another position changes the column list
+        self.column_view().columns().connect_items_changed(
+            clone!(@weak self as view => move |_, _, _, _| {
+                view.save_columns();
+            }),
+        );
+        if let Some(sorter) = self.column_view().sorter() {
+            sorter.connect_changed(clone!(@weak self as view => move |_, _| {
+                view.column_sorting_changed();
+            }));
+        }
+    }
+
+    /// Shows or hides the search bar, focusing the search entry when shown
+    pub fn toggle_search(&self) {
+        let imp = self.imp();
+        let search_mode = !imp.search_bar.is_search_mode();
+        imp.search_bar.set_search_mode(search_mode);
+        if search_mode {
+            imp.search_entry.grab_focus();
+        }
+    }
+
+    fn create_column(&self, info: &'static ColumnInfo, menu: &gio::Menu) -> ColumnViewColumn {
+        let property = info.id;
+        let editable = info.editable;
+        let column_list_factory = SignalListItemFactory::new();
+        column_list_factory.connect_setup(move |_, list_item| {
+            let tag_cell = TagCell::new();
+            tag_cell.label().set_editable(editable);
+            list_item.set_child(Some(&tag_cell));
+        });
+        column_list_factory.connect_bind(move |_, list_item| {
+            let tag_cell = list_item
+                .child()
+                .and_then(|child| child.downcast::<TagCell>().ok())
+                .expect("column child is not a TagCell");
+            if let Some(taggable_file) = list_item
+                .item()
+                .and_then(|item| item.downcast::<TaggableFile>().ok())
+            {
+                // Unsupported and unreadable files are listed, but their tags can't be edited
+                tag_cell
+                    .label()
+                    .set_editable(editable && taggable_file.is_editable());
+                tag_cell.bind(&taggable_file, property);
+            }
+        });
+        column_list_factory.connect_unbind(move |_, list_item| {
+            if let Some(tag_cell) = list_item
+                .child()
+                .and_then(|child| child.downcast::<TagCell>().ok())
+            {
+                tag_cell.unbind();
+            }
+        });
+
+        let column = ColumnViewColumn::new(
+            Some(property_title(property).as_str()),
+            Some(&column_list_factory),
+        );
+        column.set_resizable(true);
+        column.set_header_menu(Some(menu));
+
+        // Only used to show the sort indicators and to learn about clicks on the
+        // column headers, the files are sorted by `file_sorter`
+        let column_sorter = CustomSorter::new(move |a, b| compare_files(a, b, property).into());
+        column.set_sorter(Some(&column_sorter));
+        column.connect_fixed_width_notify(clone!(@weak self as view => move |_| {
+            view.save_column_widths();
+        }));
+        column
+    }
+
+    /// Adds all columns, the ones saved in the settings shown in their saved order and
+    /// with their saved widths. The others are hidden and can be shown from the menu of
+    /// any column header.
+    fn restore_columns(&self) {
+        let settings = gio::Settings::new(APP_ID);
+        let shown = settings.strv("columns");
+        let widths = settings
+            .value("column-widths")
+            .get::<HashMap<String, i32>>()
+            .unwrap_or_default();
+
+        let menu = gio::Menu::new();
+        let actions = gio::SimpleActionGroup::new();
+        let mut columns = vec![];
+        for info in COLUMNS {
+            let column = self.create_column(info, &menu);
+            let visible = shown.iter().any(|id| id == info.id);
+            column.set_visible(visible);
+            if let Some(width) = widths.get(info.id) {
+                column.set_fixed_width(*width);
+            }
+
+            menu.append(
+                Some(property_title(info.id).as_str()),
+                Some(&format!("columns.{}", info.id)),
+            );
+            let action = gio::SimpleAction::new_stateful(info.id, None, &visible.to_variant());
+            action.connect_activate(
+                clone!(@weak self as view, @weak column => move |action, _| {
+                    let visible = !action
+                        .state()
+                        .and_then(|state| state.get::<bool>())
+                        .unwrap_or_default();
+                    action.set_state(&visible.to_variant());
+                    column.set_visible(visible);
+                    view.save_columns();
+                }),
+            );
+            actions.add_action(&action);
+            columns.push((info.id, column));
+        }
+        self.insert_action_group("columns", Some(&actions));
+
+        let column_view = self.column_view();
+        for id in shown.iter() {
+            if let Some((_, column)) = columns.iter().find(|(column_id, _)| column_id == id) {
+                column_view.append_column(column);
+            }
+        }
+        for (_, column) in columns.iter().filter(|(_, column)| !column.is_visible()) {
+            column_view.append_column(column);
+        }
+        self.imp().columns.replace(columns);
+    }
+
+    /// Saves which columns are shown and their order
+    fn save_columns(&self) {
+        let ids: Vec<&str> = self
+            .visible_columns()
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect();
+        if let Err(err) = gio::Settings::new(APP_ID).set_strv("columns", &ids) {
+            log::warn!("Failed to save the columns, {}", err);
+        }
+    }
+
+    fn save_column_widths(&self) {
+        let widths: HashMap<&str, i32> = self
+            .imp()
+            .columns
+            .borrow()
+            .iter()
+            .filter(|(_, column)| column.fixed_width() > 0)
+            .map(|(id, column)| (*id, column.fixed_width()))
+            .collect();
+        if let Err(err) =
+            gio::Settings::new(APP_ID).set_value("column-widths", &widths.to_variant())
+        {
+            log::warn!("Failed to save the column widths, {}", err);
+        }
+    }
+
+    /// Sorts by the columns saved in the settings
+    fn restore_sort_order(&self) {
+        let sort_order = gio::Settings::new(APP_ID)
+            .value("sort-order")
+            .get::<Vec<(String, bool)>>()
+            .unwrap_or_default();
+        self.imp().sort_keys.replace(sort_order.clone());
+
+        // The column view can only be told about the most significant column
+        if let Some((property, descending)) = sort_order.first() {
+            let columns = self.imp().columns.borrow();
+            if let Some((_, column)) = columns.iter().find(|(id, _)| id == property) {
+                let direction = if *descending {
+                    gtk::SortType::Descending
+                } else {
+                    gtk::SortType::Ascending
+                };
+                self.column_view().sort_by_column(Some(column), direction);
+            }
+        }
+    }
+
+    /// Makes the column whose header was clicked the most significant sort key, keeping
+    /// the previous keys to sort files that are equal in that column
+    fn column_sorting_changed(&self) {
+        let imp = self.imp();
+        let sorter = match self.column_view().sorter() {
+            Some(sorter) => sorter,
+            None => return,
+        };
+
+        // The column view sorter names its most significant column since GTK 4.10
+        if sorter.find_property("primary-sort-column").is_none() {
+            log::warn!("Sorting by clicked columns needs GTK 4.10");
+            return;
+        }
+        let column = sorter.property::<Option<ColumnViewColumn>>("primary-sort-column");
+        let descending =
+            sorter.property::<gtk::SortType>("primary-sort-order") == gtk::SortType::Descending;
+        let property = column.and_then(|column| {
+            imp.columns
+                .borrow()
+                .iter()
+                .find(|(_, candidate)| *candidate == column)
+                .map(|(id, _)| id.to_string())
+        });
+
+        {
+            let mut sort_keys = imp.sort_keys.borrow_mut();
+            match property {
+                Some(property) => {
+                    sort_keys.retain(|(key, _)| *key != property);
+                    sort_keys.insert(0, (property, descending));
+                }
+                None => sort_keys.clear(),
+            }
+        }
+        self.sort_model()
+            .sorter()
+            .expect("FileColumnView has no sorter")
+            .changed(SorterChange::Different);
+
+        let sort_order = imp.sort_keys.borrow().to_variant();
+        if let Err(err) = gio::Settings::new(APP_ID).set_value("sort-order", &sort_order) {
+            log::warn!("Failed to save the sort order, {}", err);
+        }
+    }
+}
+
+fn compare_files(a: &glib::Object, b: &glib::Object, property: &str) -> Ordering {
+    let (a, b) = match (
+        a.downcast_ref::<TaggableFile>(),
+        b.downcast_ref::<TaggableFile>(),
+    ) {
+        (Some(a), Some(b)) => (a, b),
+        _ => return Ordering::Equal,
+    };
+    match property {
+        "filename" | "path" => natural_cmp(&a.property_text(property), &b.property_text(property)),
+        _ => compare_field(&a.borrow_record(), &b.borrow_record(), property),
     }
 }
 
@@ -0,0 +1,146 @@
+use crate::taggablefile::{is_valid_value, TaggableFile};
+use gtk::{
+    glib, glib::clone, glib::Binding, glib::BindingFlags, prelude::*, subclass::prelude::*,
+    CompositeTemplate, EditableLabel, Widget,
+};
+use std::cell::RefCell;
+
+mod imp {
+    use super::*;
+
+    #[derive(CompositeTemplate, Default)]
+    #[template(resource = "/net/fhannenheim/EasierTag/ui/tagcell.ui")]
+    pub struct TagCell {
+        #[template_child]
+        pub label: TemplateChild<EditableLabel>,
+        pub binding: RefCell<Option<Binding>>,
+        pub taggable_file: RefCell<Option<TaggableFile>>,
+        pub property: RefCell<String>,
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for TagCell {
+        // `NAME` needs to match `class` attribute of template
+        const NAME: &'static str = "TagCell";
+        type Type = super::TagCell;
+        type ParentType = Widget;
+
+        fn class_init(klass: &mut Self::Class) {
+            klass.bind_template();
+        }
+
+        fn instance_init(obj: &glib::subclass::InitializingObject<Self>) {
+            obj.init_template();
+        }
+    }
+
+    impl ObjectImpl for TagCell {
+        fn constructed(&self) {
+            self.parent_constructed();
+            let obj = self.obj();
+
+            self.label
+                .connect_changed(clone!(@weak obj => move |label| {
+                    if is_valid_value(&obj.imp().property.borrow(), &label.text()) {
+                        label.remove_css_class("error");
+                    } else {
+                        label.add_css_class("error");
+                    }
+                }));
+            // The edit is written to the TaggableFile once editing ends, so that it is a
+            // single undo step. Invalid input never reaches it, its value is shown again.
+            self.label
+                .connect_editing_notify(clone!(@weak obj => move |label| {
+                    if label.is_editing() {
+                        return;
+                    }
+                    if label.has_css_class("error") {
+                        obj.rebind();
+                    } else {
+                        obj.commit();
+                    }
+                }));
+        }
+        fn dispose(&self) {
+            while let Some(child) = self.obj().first_child() {
+                child.unparent();
+            }
+        }
+    }
+
+    impl WidgetImpl for TagCell {}
+}
+
+glib::wrapper! {
+    pub struct TagCell(ObjectSubclass<imp::TagCell>)
+        @extends Widget;
+}
+
+impl TagCell {
+    pub fn new() -> Self {
+        glib::Object::builder().build()
+    }
+
+    pub fn label(&self) -> EditableLabel {
+        self.imp().label.clone()
+    }
+
+    /// Shows `property` of `taggable_file`, edits are written back to it by `commit`
+    pub fn bind(&self, taggable_file: &TaggableFile, property: &str) {
+        self.unbind();
+        let imp = self.imp();
+
+        let binding = match property {
+            "artists" | "album-artists" => taggable_file
+                .bind_property(property, &imp.label.get(), "text")
+                .transform_to(|_, list: Vec<String>| Some(list.join(", ")))
+                .flags(BindingFlags::SYNC_CREATE)
+                .build(),
+            _ => taggable_file
+                .bind_property(property, &imp.label.get(), "text")
+                .transform_to(|_, value: Option<String>| Some(value.unwrap_or_default()))
+                .flags(BindingFlags::SYNC_CREATE)
+                .build(),
+        };
+
+        imp.binding.replace(Some(binding));
+        imp.taggable_file.replace(Some(taggable_file.clone()));
+        imp.property.replace(property.to_owned());
+    }
+
+    pub fn unbind(&self) {
+        let imp = self.imp();
+        if let Some(binding) = imp.binding.take() {
+            binding.unbind();
+        }
+        imp.taggable_file.take();
+        imp.label.remove_css_class("error");
+    }
+
+    /// Writes the edited text to the bound file, unless it is unchanged
+    fn commit(&self) {
+        let imp = self.imp();
+        let taggable_file = imp.taggable_file.borrow().clone();
+        if let Some(taggable_file) = taggable_file {
+            let property = imp.property.borrow().clone();
+            let text = imp.label.text();
+            if imp.label.is_editable() && text != taggable_file.property_text(&property) {
+                taggable_file.set_property_text(&property, &text);
+            }
+        }
+    }
+
+    fn rebind(&self) {
+        let taggable_file = self.imp().taggable_file.borrow().clone();
+        if let Some(taggable_file) = taggable_file {
+            let property = self.imp().property.borrow().clone();
+            self.bind(&taggable_file, &property);
+        }
+    }
+}
+
+impl Default for TagCell {
+    fn default() -> Self {
+        Self::new()
+    }
+}
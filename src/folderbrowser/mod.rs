@@ -1,24 +1,40 @@
 use gtk::{
-    gio, glib, glib::clone, glib::closure, glib::Object, prelude::*, subclass::prelude::*,
-    BitsetIter, CompositeTemplate, ConstantExpression, CustomSorter, DirectoryList, FileFilter,
-    FilterChange, FilterListModel, ListItem, ListView, MultiSelection, PropertyExpression,
-    SignalListItemFactory, SingleSelection, SortListModel, TreeListModel, TreeListRow, Widget,
+    gio, glib, glib::clone, glib::closure, glib::Object, prelude::*, subclass::prelude::*, Bitset,
+    CompositeTemplate, ConstantExpression, CustomSorter, DirectoryList, FileFilter, FilterChange,
+    FilterListModel, ListItem, ListView, MultiSelection, PropertyExpression, SignalListItemFactory,
+    SortListModel, TreeListModel, TreeListRow, Widget,
 };
 
+use crate::config::APP_ID;
 use crate::folderbrowser::folderitem::FolderItem;
 use crate::taggablefile::taggablefilelist::TaggableFileListModel;
 use crate::window::EasierTagApplicationWindow;
+use gettextrs::gettext;
 mod folderitem;
 
 mod imp {
     use super::*;
+    use std::cell::{Cell, RefCell};
 
     #[derive(CompositeTemplate)]
     #[template(resource = "/net/fhannenheim/EasierTag/ui/folderbrowser.ui")]
     pub struct FolderBrowser {
+        #[template_child]
+        pub root_label: TemplateChild<gtk::Label>,
+        #[template_child]
+        pub bookmark_button: TemplateChild<gtk::Button>,
+        #[template_child]
+        pub bookmarks_listbox: TemplateChild<gtk::ListBox>,
         #[template_child]
         pub primary_listview: TemplateChild<ListView>,
         pub primary_dirlist: DirectoryList,
+        pub settings: gio::Settings,
+        // Kept alive while the file chooser is shown
+        pub file_chooser: RefCell<Option<gtk::FileChooserNative>>,
+        // Folders whose files are currently loaded into the file list. Stored as files
+        // rather than rows, which move when folders are expanded or collapsed.
+        pub selection: RefCell<Vec<gio::File>>,
+        pub restoring_selection: Cell<bool>,
     }
 
     impl Default for FolderBrowser {
@@ -28,8 +44,15 @@ mod imp {
             primary_dirlist.set_monitored(true);
 
             Self {
+                root_label: TemplateChild::default(),
+                bookmark_button: TemplateChild::default(),
+                bookmarks_listbox: TemplateChild::default(),
                 primary_listview: TemplateChild::<ListView>::default(),
                 primary_dirlist,
+                settings: gio::Settings::new(APP_ID),
+                file_chooser: RefCell::new(None),
+                selection: RefCell::new(vec![]),
+                restoring_selection: Cell::new(false),
             }
         }
     }
@@ -100,61 +123,22 @@ impl FolderBrowser {
                 PropertyExpression::new(ListItem::static_type(), Some(&list_item_expr), "item");
 
             let icon_name_expr =
-                fileinfo_expr.chain_closure::<gio::ThemedIcon>(closure!(|_: Option<
-                    glib::Object,
-                >,
-                                                                         fileinfo_obj: Option<
-                    glib::Object,
+                fileinfo_expr.chain_closure::<gio::Icon>(closure!(|_: Option<Object>,
+                                                                   fileinfo_obj: Option<
+                    Object,
                 >| {
-                    if let Some(fileinfo_obj) = fileinfo_obj {
-                        if let Some(themed_icon) = fileinfo_obj
-                            .downcast::<TreeListRow>()
-                            .unwrap()
-                            .item()
-                            .unwrap()
-                            .downcast::<gio::FileInfo>()
-                            .unwrap()
-                            .attribute_object("standard::symbolic-icon")
-                        {
-                            return themed_icon.downcast::<gio::ThemedIcon>().unwrap();
-                        }
-                    }
-
-                    gio::ThemedIcon::from_names(&[
-                        "workspace-folder-symbolic",
-                        "folder-documents-symbolic",
-                    ])
+                    folder_icon(fileinfo_obj.as_ref())
                 }));
 
-            let basename_expr =
-                fileinfo_expr.chain_closure::<String>(closure!(|_: Option<glib::Object>,
-                                                                fileinfo_obj: Option<
-                    glib::Object,
-                >| {
-                    if let Some(fileinfo_obj) = fileinfo_obj {
-                        if let Some(file) = fileinfo_obj
-                            .downcast::<TreeListRow>()
-                            .unwrap()
-                            .item()
-                            .unwrap()
-                            .downcast::<gio::FileInfo>()
-                            .unwrap()
-                            .attribute_object("standard::file")
-                        {
-                            let file = file
-                                .downcast::<gio::File>()
-                                .expect("failed to downcast::<gio::File>() from file GObject");
-
-                            return String::from(
-                                file.basename()
-                                    .expect("failed to get file.basename()")
-                                    .to_string_lossy(),
-                            );
-                        }
-                    }
-
-                    String::from("")
-                }));
+            let basename_expr = fileinfo_expr.chain_closure::<String>(closure!(
+                |_: Option<Object>, fileinfo_obj: Option<Object>| {
+                    fileinfo_obj
+                        .as_ref()
+                        .and_then(info_file)
+                        .map(|file| file_name(&file))
+                        .unwrap_or_default()
+                }
+            ));
 
             let treeexpander_expr = fileinfo_expr.chain_closure::<Option<TreeListRow>>(closure!(
                 |_: Option<Object>, fileinfo_obj: Option<Object>| {
@@ -178,25 +162,9 @@ impl FolderBrowser {
             FilterListModel::new(Some(&self.imp().primary_dirlist), Some(&filefilter));
 
         let alphanumeric_sorter = CustomSorter::new(move |obj1, obj2| {
-            let first_fileinfo = obj1
-                .clone()
-                .downcast::<gio::FileInfo>()
-                .expect("failed to downcast obj1");
-            let first_file = first_fileinfo.attribute_object("standard::file").unwrap();
-            let first_file = first_file.downcast::<gio::File>().unwrap();
-            let first_display_name = first_file.basename().unwrap();
-            let first_display_name = first_display_name.to_str().unwrap();
-
-            let second_fileinfo = obj2
-                .clone()
-                .downcast::<gio::FileInfo>()
-                .expect("failed to downcast obj2");
-            let second_file = second_fileinfo.attribute_object("standard::file").unwrap();
-            let second_file = second_file.downcast::<gio::File>().unwrap();
-            let second_display_name = second_file.basename().unwrap();
-            let second_display_name = second_display_name.to_str().unwrap();
-
-            first_display_name.cmp(second_display_name).into()
+            let first_display_name = info_file(obj1).map(|file| file_name(&file));
+            let second_display_name = info_file(obj2).map(|file| file_name(&file));
+            first_display_name.cmp(&second_display_name).into()
         });
         let sort_list_model =
             SortListModel::new(Some(&filefilter_model), Some(&alphanumeric_sorter));
@@ -206,13 +174,7 @@ impl FolderBrowser {
             false,
             false,
             clone!(@weak filefilter, @weak alphanumeric_sorter => @default-return None, move |obj| {
-                    let fileinfo = obj
-                        .clone()
-                        .downcast::<gio::FileInfo>()
-                        .unwrap()
-                        .attribute_object("standard::file")
-                        .unwrap();
-                    if let Ok(file) = fileinfo.downcast::<gio::File>() {
+                    if let Some(file) = info_file(obj) {
                         let secondary_dirlist = DirectoryList::new(Some("standard::*"), Some(&file));
                         secondary_dirlist.set_monitored(true);
                         let secondary_filefiltermodel = FilterListModel::new(Some(&secondary_dirlist), Some(&filefilter));
@@ -238,36 +200,279 @@ impl FolderBrowser {
                 filefilter.changed(FilterChange::Different);
             }),
         );
-        primary_selection_model.connect_selection_changed(clone!(@weak window => move |_model, _position, _n| {
-            let filelist = window.filecolumnview().column_view().model().unwrap().downcast::<MultiSelection>().unwrap().model().unwrap().downcast::<TaggableFileListModel>().unwrap();
-            filelist.clear_folders();
-            if let Some(model) = _model.model() {
-                for index in BitsetIter::init_first(&_model.selection()) {
-                    let item = _model.item(index.1);
-                    if let Some(fileinfo_obj) = item {
-                        if let Some(file) = fileinfo_obj
-                            .downcast::<TreeListRow>()
-                            .unwrap()
-                            .item()
-                            .unwrap()
-                            .downcast::<gio::FileInfo>()
-                            .unwrap()
-                            .attribute_object("standard::file")
-                            {
-                                let file = file
-                                    .downcast::<gio::File>()
-                                    .expect("failed to downcast::<gio::File>() from file GObject");
-                                println!("adding {}", file.basename().unwrap().display());
-                                filelist.add_folder(&file);
-                            }
+        primary_selection_model.connect_selection_changed(
+            clone!(@weak self as folderbrowser, @weak window => move |model, _position, _n| {
+                if folderbrowser.imp().restoring_selection.get() {
+                    return;
+                }
+                window.confirm_unsaved_changes(clone!(@weak folderbrowser, @weak window, @weak model => move |proceed| {
+                    let imp = folderbrowser.imp();
+                    if proceed {
+                        imp.selection.replace(selected_folders(&model));
+                        folderbrowser.load_selected_folders(&model, &window.filecolumnview().file_list());
+                    } else {
+                        // Go back to the folders whose files are still shown
+                        imp.restoring_selection.set(true);
+                        folderbrowser.restore_selection(&model);
+                        imp.restoring_selection.set(false);
                     }
+                }));
+            }),
+        );
+
+        let imp = self.imp();
+        imp.bookmark_button
+            .connect_clicked(clone!(@weak self as folderbrowser => move |_| {
+                folderbrowser.add_bookmark(&folderbrowser.root_folder());
+            }));
+        imp.bookmarks_listbox.connect_row_activated(
+            clone!(@weak self as folderbrowser => move |_, row| {
+                if let Some(bookmark) = folderbrowser.bookmarks().get(row.index() as usize) {
+                    folderbrowser.open_folder(bookmark);
                 }
-                filelist.rebuild_taglist();
+            }),
+        );
+        imp.settings.connect_changed(
+            Some("bookmarks"),
+            clone!(@weak self as folderbrowser => move |_, _| {
+                folderbrowser.update_bookmarks();
+            }),
+        );
+
+        self.set_root(&self.root_folder());
+    }
+
+    /// Unselects all folders, removing their files from the file list
+    pub fn clear_selection(&self) {
+        if let Some(selection_model) = self.imp().primary_listview.model() {
+            selection_model.unselect_all();
+        }
+    }
+
+    /// The folder shown in the browser: the last opened folder, or the music folder
+    pub fn root_folder(&self) -> gio::File {
+        let root_folder = self.imp().settings.string("root-folder");
+        if !root_folder.is_empty() {
+            return gio::File::for_parse_name(&root_folder);
+        }
+        gio::File::for_path(
+            glib::user_special_dir(glib::UserDirectory::Music).unwrap_or_else(glib::home_dir),
+        )
+    }
+
+    /// Shows `folder` in the browser after the user saved or discarded unsaved changes
+    pub fn open_folder(&self, folder: &gio::File) {
+        let window = match self
+            .root()
+            .and_then(|root| root.downcast::<EasierTagApplicationWindow>().ok())
+        {
+            Some(window) => window,
+            None => return,
+        };
+        window.confirm_unsaved_changes(
+            clone!(@weak self as folderbrowser, @weak window, @strong folder => move |proceed| {
+                if proceed {
+                    window.filecolumnview().file_list().clear_folders();
+                    folderbrowser.imp().selection.replace(vec![]);
+                    folderbrowser.set_root(&folder);
+                }
+            }),
+        );
+    }
+
+    /// Lets the user pick the folder to show in the browser
+    pub fn choose_folder(&self) {
+        let window = self
+            .root()
+            .and_then(|root| root.downcast::<gtk::Window>().ok());
+        let file_chooser = gtk::FileChooserNative::new(
+            Some(gettext("Open Folder").as_str()),
+            window.as_ref(),
+            gtk::FileChooserAction::SelectFolder,
+            Some(gettext("_Open").as_str()),
+            Some(gettext("_Cancel").as_str()),
+        );
+        file_chooser.set_modal(true);
+        if let Err(err) = file_chooser.set_current_folder(Some(&self.root_folder())) {
+            log::debug!("Failed to preselect the root folder, {}", err);
+        }
+
+        file_chooser.connect_response(
+            clone!(@weak self as folderbrowser => move |file_chooser, response| {
+                if response == gtk::ResponseType::Accept {
+                    if let Some(folder) = file_chooser.file() {
+                        folderbrowser.open_folder(&folder);
+                    }
+                }
+                folderbrowser.imp().file_chooser.replace(None);
+            }),
+        );
+        file_chooser.show();
+        self.imp().file_chooser.replace(Some(file_chooser));
+    }
+
+    fn set_root(&self, folder: &gio::File) {
+        let imp = self.imp();
+        if let Err(err) = imp.settings.set_string("root-folder", &folder.parse_name()) {
+            log::warn!("Failed to remember the root folder, {}", err);
+        }
+
+        imp.root_label.set_label(&file_name(folder));
+        imp.root_label
+            .set_tooltip_text(Some(folder.parse_name().as_str()));
+        imp.primary_dirlist.set_file(Some(folder));
+        self.update_bookmarks();
+    }
+
+    /// Folders pinned to the browser, in the order they were added
+    pub fn bookmarks(&self) -> Vec<gio::File> {
+        self.imp()
+            .settings
+            .strv("bookmarks")
+            .iter()
+            .map(|bookmark| gio::File::for_parse_name(bookmark))
+            .collect()
+    }
+
+    fn set_bookmarks(&self, bookmarks: &[gio::File]) {
+        let parse_names = bookmarks
+            .iter()
+            .map(|bookmark| bookmark.parse_name())
+            .collect::<Vec<glib::GString>>();
+        let parse_names = parse_names
+            .iter()
+            .map(|parse_name| parse_name.as_str())
+            .collect::<Vec<&str>>();
+        if let Err(err) = self.imp().settings.set_strv("bookmarks", &parse_names) {
+            log::warn!("Failed to save the bookmarks, {}", err);
+        }
+    }
+
+    pub fn add_bookmark(&self, folder: &gio::File) {
+        let mut bookmarks = self.bookmarks();
+        if !bookmarks.iter().any(|bookmark| bookmark.equal(folder)) {
+            bookmarks.push(folder.clone());
+            self.set_bookmarks(&bookmarks);
+        }
+    }
+
+    pub fn remove_bookmark(&self, folder: &gio::File) {
+        let mut bookmarks = self.bookmarks();
+        bookmarks.retain(|bookmark| !bookmark.equal(folder));
+        self.set_bookmarks(&bookmarks);
+    }
+
+    fn update_bookmarks(&self) {
+        let imp = self.imp();
+        while let Some(row) = imp.bookmarks_listbox.first_child() {
+            imp.bookmarks_listbox.remove(&row);
+        }
+
+        let bookmarks = self.bookmarks();
+        for bookmark in bookmarks.iter() {
+            let label = gtk::Label::builder()
+                .label(&file_name(bookmark))
+                .tooltip_text(bookmark.parse_name().as_str())
+                .ellipsize(gtk::pango::EllipsizeMode::Middle)
+                .halign(gtk::Align::Start)
+                .hexpand(true)
+                .build();
+            let remove_button = gtk::Button::builder()
+                .icon_name("window-close-symbolic")
+                .tooltip_text(&gettext("Remove Bookmark"))
+                .css_classes(vec![String::from("flat")])
+                .build();
+            remove_button.connect_clicked(
+                clone!(@weak self as folderbrowser, @strong bookmark => move |_| {
+                    folderbrowser.remove_bookmark(&bookmark);
+                }),
+            );
+
+            let row_box = gtk::Box::new(gtk::Orientation::Horizontal, 6);
+            row_box.append(&gtk::Image::from_icon_name("starred-symbolic"));
+            row_box.append(&label);
+            row_box.append(&remove_button);
+            imp.bookmarks_listbox.append(&row_box);
+        }
+        imp.bookmarks_listbox.set_visible(!bookmarks.is_empty());
+
+        let root_folder = self.root_folder();
+        imp.bookmark_button.set_sensitive(
+            !bookmarks
+                .iter()
+                .any(|bookmark| bookmark.equal(&root_folder)),
+        );
+    }
+
+    fn load_selected_folders(
+        &self,
+        selection_model: &MultiSelection,
+        filelist: &TaggableFileListModel,
+    ) {
+        filelist.clear_folders();
+        for file in selected_folders(selection_model) {
+            log::debug!("Adding {}", file.parse_name());
+            filelist.add_folder(&file);
+        }
+    }
+
+    /// Selects the rows of the folders whose files are loaded, wherever they are now
+    fn restore_selection(&self, selection_model: &MultiSelection) {
+        let folders = self.imp().selection.borrow();
+        let selection = Bitset::new_empty();
+        for position in 0..selection_model.n_items() {
+            let file = selection_model.item(position).as_ref().and_then(info_file);
+            if file.is_some_and(|file| folders.iter().any(|folder| folder.equal(&file))) {
+                selection.add(position);
             }
-        }));
+        }
+        selection_model.set_selection(&selection, &Bitset::new_range(0, selection_model.n_items()));
+    }
+}
 
-        self.primary_dirlist()
-            .set_file(Some(&gio::File::for_path("/home/fried/")));
-        println!("{:?}", self.imp().primary_dirlist.is_loading());
+/// The folders of the selected rows of the folder tree
+fn selected_folders(selection_model: &MultiSelection) -> Vec<gio::File> {
+    if selection_model.model().is_none() {
+        return vec![];
     }
+    let selection = selection_model.selection();
+    (0..selection.size() as u32)
+        .map(|index| selection.nth(index))
+        .filter_map(|position| selection_model.item(position).as_ref().and_then(info_file))
+        .collect()
+}
+
+/// The file info behind an item of the folder tree, which is either a `FileInfo` of a
+/// `DirectoryList` or a `TreeListRow` holding one
+fn file_info(item: &Object) -> Option<gio::FileInfo> {
+    match item.downcast_ref::<TreeListRow>() {
+        Some(tree_list_row) => tree_list_row.item()?.downcast::<gio::FileInfo>().ok(),
+        None => item.clone().downcast::<gio::FileInfo>().ok(),
+    }
+}
+
+/// The icon of an item of the folder tree, a generic folder icon if it has none
+fn folder_icon(item: Option<&Object>) -> gio::Icon {
+    item.and_then(file_info)
+        .and_then(|fileinfo| fileinfo.attribute_object("standard::symbolic-icon"))
+        .and_then(|icon| icon.downcast::<gio::Icon>().ok())
+        .unwrap_or_else(|| {
+            gio::ThemedIcon::from_names(&["workspace-folder-symbolic", "folder-documents-symbolic"])
+                .upcast()
+        })
+}
+
+/// The file an item of the folder tree refers to
+fn info_file(item: &Object) -> Option<gio::File> {
+    file_info(item)?
+        .attribute_object("standard::file")?
+        .downcast::<gio::File>()
+        .ok()
+}
+
+/// The name of `file` as shown in the folder browser, with invalid UTF-8 replaced
+fn file_name(file: &gio::File) -> String {
+    file.basename()
+        .map(|basename| basename.to_string_lossy().into_owned())
+        .unwrap_or_else(|| file.parse_name().to_string())
 }
@@ -1,9 +1,14 @@
 mod application;
+mod cli;
 #[rustfmt::skip]
 mod config;
+mod coverpanel;
 mod filecolumnview;
 mod folderbrowser;
+mod renamefiles;
+mod tageditor;
 mod taggablefile;
+mod tagsfromfilename;
 mod window;
 
 use gettextrs::{gettext, LocaleCategory};
@@ -21,6 +26,12 @@ fn main() {
     gettextrs::bindtextdomain(GETTEXT_PACKAGE, LOCALEDIR).expect("Unable to bind the text domain");
     gettextrs::textdomain(GETTEXT_PACKAGE).expect("Unable to switch to the text domain");
 
+    // `easier-tag tag <command> …` reads and writes tags without starting the GUI
+    let args = std::env::args_os().collect::<Vec<_>>();
+    if cli::is_command(args.get(1..).unwrap_or_default()) {
+        std::process::exit(cli::run(&args[2..]));
+    }
+
     glib::set_application_name(&gettext("EasierTag"));
 
     let res = gio::Resource::load(RESOURCES_FILE).expect("Could not load gresource file");
@@ -0,0 +1,309 @@
+use crate::config::APP_ID;
+use crate::taggablefile::pattern::{format_path, PLACEHOLDERS};
+use crate::taggablefile::TaggableFile;
+use gettextrs::gettext;
+use gtk::{
+    gio, glib, glib::clone, prelude::*, subclass::prelude::*, Button, CompositeTemplate, Entry,
+    Grid, Label,
+};
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::path::{Component, Path, PathBuf};
+
+mod imp {
+    use super::*;
+
+    #[derive(CompositeTemplate)]
+    #[template(resource = "/net/fhannenheim/EasierTag/ui/renamefiles.ui")]
+    pub struct RenameFilesDialog {
+        #[template_child]
+        pub template_entry: TemplateChild<Entry>,
+        #[template_child]
+        pub placeholders_label: TemplateChild<Label>,
+        #[template_child]
+        pub preview_grid: TemplateChild<Grid>,
+        #[template_child]
+        pub cancel_button: TemplateChild<Button>,
+        #[template_child]
+        pub apply_button: TemplateChild<Button>,
+
+        pub files: RefCell<Vec<TaggableFile>>,
+        pub settings: gio::Settings,
+    }
+
+    impl Default for RenameFilesDialog {
+        fn default() -> Self {
+            Self {
+                template_entry: TemplateChild::default(),
+                placeholders_label: TemplateChild::default(),
+                preview_grid: TemplateChild::default(),
+                cancel_button: TemplateChild::default(),
+                apply_button: TemplateChild::default(),
+                files: RefCell::new(vec![]),
+                settings: gio::Settings::new(APP_ID),
+            }
+        }
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for RenameFilesDialog {
+        // `NAME` needs to match `class` attribute of template
+        const NAME: &'static str = "RenameFilesDialog";
+        type Type = super::RenameFilesDialog;
+        type ParentType = gtk::Window;
+
+        fn class_init(klass: &mut Self::Class) {
+            klass.bind_template();
+        }
+
+        fn instance_init(obj: &glib::subclass::InitializingObject<Self>) {
+            obj.init_template();
+        }
+    }
+
+    impl ObjectImpl for RenameFilesDialog {
+        fn constructed(&self) {
+            self.parent_constructed();
+            let obj = self.obj();
+
+            let placeholders = PLACEHOLDERS
+                .iter()
+                .map(|(placeholder, _)| format!("%{}%", placeholder))
+                .collect::<Vec<String>>()
+                .join(" ");
+            self.placeholders_label.set_label(&format!(
+                "{} {}\n{}",
+                gettext("Available placeholders:"),
+                placeholders,
+                gettext(
+                    "Numbers can be padded with zeros like %track:02%. Paths are relative to the folder of each file, use / to move files into subfolders and .. to move them up."
+                )
+            ));
+
+            self.template_entry
+                .set_text(&self.settings.string("rename-template"));
+            self.template_entry
+                .connect_changed(clone!(@weak obj => move |_| obj.update_preview()));
+            self.template_entry
+                .connect_activate(clone!(@weak obj => move |_| obj.apply()));
+            self.cancel_button
+                .connect_clicked(clone!(@weak obj => move |_| obj.close()));
+            self.apply_button
+                .connect_clicked(clone!(@weak obj => move |_| obj.apply()));
+        }
+    }
+
+    impl WidgetImpl for RenameFilesDialog {}
+    impl WindowImpl for RenameFilesDialog {}
+}
+
+glib::wrapper! {
+    pub struct RenameFilesDialog(ObjectSubclass<imp::RenameFilesDialog>)
+        @extends gtk::Widget, gtk::Window;
+}
+
+/// Where a file would be moved and why it can't be, if it can't
+struct Rename {
+    taggable_file: TaggableFile,
+    old_path: PathBuf,
+    new_path: PathBuf,
+    problem: Option<String>,
+}
+
+/// Resolves `.` and `..` without touching the filesystem, so that paths can be compared
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+impl RenameFilesDialog {
+    pub fn new(parent: &impl IsA<gtk::Window>, files: Vec<TaggableFile>) -> Self {
+        let dialog: Self = glib::Object::builder()
+            .property("transient-for", parent)
+            .build();
+        dialog.imp().files.replace(files);
+        dialog.update_preview();
+        dialog
+    }
+
+    fn template(&self) -> String {
+        self.imp().template_entry.text().to_string()
+    }
+
+    /// Computes the new path of every file without renaming anything
+    fn plan(&self) -> Option<Vec<Rename>> {
+        let template = self.template();
+        if template.trim().is_empty() {
+            return None;
+        }
+
+        let mut renames = vec![];
+        // Compared case-insensitively, as not all filesystems tell the cases apart
+        let mut targets = HashSet::new();
+        for taggable_file in self.imp().files.borrow().iter() {
+            let old_path = taggable_file.path();
+            let extension = old_path
+                .extension()
+                .map(|extension| extension.to_string_lossy().into_owned());
+            let relative_path = format_path(
+                &template,
+                |property| taggable_file.property_text(property),
+                extension.as_deref(),
+            )?;
+            let new_path = normalize(
+                &old_path
+                    .parent()
+                    .map(|parent| parent.join(&relative_path))
+                    .unwrap_or(relative_path),
+            );
+
+            let problem = if new_path == old_path {
+                None
+            } else if !targets.insert(new_path.to_string_lossy().to_lowercase()) {
+                Some(gettext("Another file gets the same name"))
+            } else if new_path.exists() {
+                Some(gettext("A file with this name already exists"))
+            } else {
+                None
+            };
+
+            renames.push(Rename {
+                taggable_file: taggable_file.clone(),
+                old_path,
+                new_path,
+                problem,
+            });
+        }
+        Some(renames)
+    }
+
+    /// Shows the new name of every file, this is a dry run that does not touch any file
+    fn update_preview(&self) {
+        let imp = self.imp();
+        let grid = imp.preview_grid.get();
+        while let Some(child) = grid.first_child() {
+            grid.remove(&child);
+        }
+
+        let attach = |column: i32, row: i32, text: &str, css_class: Option<&str>| {
+            let label = Label::new(Some(text));
+            label.set_xalign(0.0);
+            label.set_ellipsize(gtk::pango::EllipsizeMode::Middle);
+            if let Some(css_class) = css_class {
+                label.add_css_class(css_class);
+            }
+            grid.attach(&label, column, row, 1, 1);
+        };
+        attach(0, 0, &gettext("Current Name"), Some("heading"));
+        attach(1, 0, &gettext("New Name"), Some("heading"));
+
+        let renames = match self.plan() {
+            Some(renames) => renames,
+            None => {
+                attach(0, 1, &gettext("Invalid template"), Some("error"));
+                imp.apply_button.set_sensitive(false);
+                return;
+            }
+        };
+
+        let mut renamable = false;
+        for (row, rename) in renames.iter().enumerate() {
+            let row = row as i32 + 1;
+            let base = rename.old_path.parent().unwrap_or_else(|| Path::new(""));
+            attach(
+                0,
+                row,
+                &rename.taggable_file.property::<String>("filename"),
+                None,
+            );
+            let new_name = rename
+                .new_path
+                .strip_prefix(base)
+                .unwrap_or(&rename.new_path)
+                .display()
+                .to_string();
+            match &rename.problem {
+                Some(problem) => attach(
+                    1,
+                    row,
+                    &format!("{} ({})", new_name, problem),
+                    Some("error"),
+                ),
+                None if rename.new_path == rename.old_path => {
+                    attach(1, row, &new_name, Some("dim-label"))
+                }
+                None => {
+                    renamable = true;
+                    attach(1, row, &new_name, None)
+                }
+            }
+        }
+
+        imp.apply_button.set_sensitive(renamable);
+    }
+
+    /// Renames all files without problems as one undo step
+    fn apply(&self) {
+        let imp = self.imp();
+        let renames = match self.plan() {
+            Some(renames) => renames,
+            None => return,
+        };
+
+        let mut failed = vec![];
+        let mut rename_files = || {
+            for rename in renames.iter() {
+                if rename.problem.is_some() {
+                    continue;
+                }
+                if let Err(err) = rename.taggable_file.rename_to(&rename.new_path) {
+                    log::warn!(
+                        "Failed to rename {} to {}, {}",
+                        rename.old_path.display(),
+                        rename.new_path.display(),
+                        err
+                    );
+                    failed.push(format!("{}: {}", rename.old_path.display(), err));
+                }
+            }
+        };
+        match renames
+            .first()
+            .and_then(|rename| rename.taggable_file.history())
+        {
+            Some(history) => history.group(rename_files),
+            None => rename_files(),
+        }
+
+        if let Err(err) = imp.settings.set_string("rename-template", &self.template()) {
+            log::warn!("Failed to save rename template, {}", err);
+        }
+
+        if !failed.is_empty() {
+            let dialog = gtk::MessageDialog::builder()
+                .transient_for(
+                    &self
+                        .transient_for()
+                        .unwrap_or_else(|| self.clone().upcast()),
+                )
+                .modal(true)
+                .message_type(gtk::MessageType::Error)
+                .buttons(gtk::ButtonsType::Close)
+                .text(&gettext("Some files could not be renamed"))
+                .secondary_text(&failed.join("\n"))
+                .build();
+            dialog.connect_response(|dialog, _| dialog.close());
+            dialog.present();
+        }
+        self.close();
+    }
+}
@@ -0,0 +1,467 @@
+use crate::taggablefile::fields::{is_valid_key, Fields};
+use crate::taggablefile::{is_valid_value, property_title, TaggableFile};
+use gettextrs::gettext;
+use gtk::{
+    glib, glib::clone, glib::SignalHandlerId, prelude::*, subclass::prelude::*, Button,
+    CompositeTemplate, Entry, Grid, Label, ListBox, Widget,
+};
+use std::cell::{Cell, RefCell};
+use std::collections::HashSet;
+
+mod imp {
+    use super::*;
+
+    #[derive(CompositeTemplate, Default)]
+    #[template(resource = "/net/fhannenheim/EasierTag/ui/tageditor.ui")]
+    pub struct TagEditor {
+        #[template_child]
+        pub fields_grid: TemplateChild<Grid>,
+        #[template_child]
+        pub details_grid: TemplateChild<Grid>,
+        #[template_child]
+        pub extra_label: TemplateChild<Label>,
+        #[template_child]
+        pub extra_listbox: TemplateChild<ListBox>,
+        #[template_child]
+        pub add_extra_box: TemplateChild<gtk::Box>,
+        #[template_child]
+        pub extra_key_entry: TemplateChild<Entry>,
+        #[template_child]
+        pub extra_value_entry: TemplateChild<Entry>,
+        #[template_child]
+        pub add_extra_button: TemplateChild<Button>,
+        #[template_child]
+        pub apply_button: TemplateChild<Button>,
+        #[template_child]
+        pub reset_button: TemplateChild<Button>,
+
+        pub entries: RefCell<Vec<(&'static str, Entry)>>,
+        pub details: RefCell<Vec<(&'static str, Label)>>,
+        // Fields the user edited since the selection last changed
+        pub touched: RefCell<HashSet<&'static str>>,
+        pub files: RefCell<Vec<TaggableFile>>,
+        pub notify_handlers: RefCell<Vec<(TaggableFile, SignalHandlerId)>>,
+        // Set while the entries are filled from the files so that this is not seen as an edit
+        pub updating: Cell<bool>,
+        // The other fields the rows in `extra_listbox` were created for
+        pub shown_extra: RefCell<Option<Fields>>,
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for TagEditor {
+        // `NAME` needs to match `class` attribute of template
+        const NAME: &'static str = "TagEditor";
+        type Type = super::TagEditor;
+        type ParentType = Widget;
+
+        fn class_init(klass: &mut Self::Class) {
+            klass.bind_template();
+        }
+
+        fn instance_init(obj: &glib::subclass::InitializingObject<Self>) {
+            obj.init_template();
+        }
+    }
+
+    impl ObjectImpl for TagEditor {
+        fn constructed(&self) {
+            self.parent_constructed();
+            let obj = self.obj();
+
+            for (row, property) in FIELDS.iter().copied().enumerate() {
+                let label = Label::new(Some(property_title(property).as_str()));
+                label.set_halign(gtk::Align::End);
+                label.add_css_class("dim-label");
+                let entry = Entry::new();
+                entry.set_hexpand(true);
+                label.set_mnemonic_widget(Some(&entry));
+
+                entry.connect_changed(clone!(@weak obj => move |entry| {
+                    obj.field_changed(property, entry);
+                }));
+                entry.connect_activate(clone!(@weak obj => move |_| {
+                    obj.apply();
+                }));
+
+                self.fields_grid.attach(&label, 0, row as i32, 1, 1);
+                self.fields_grid.attach(&entry, 1, row as i32, 1, 1);
+                self.entries.borrow_mut().push((property, entry));
+            }
+
+            for (row, property) in DETAILS.iter().copied().enumerate() {
+                let label = Label::new(Some(property_title(property).as_str()));
+                label.set_halign(gtk::Align::End);
+                label.add_css_class("dim-label");
+                let value = Label::new(None);
+                value.set_halign(gtk::Align::Start);
+                value.set_hexpand(true);
+                value.set_selectable(true);
+                value.set_wrap(true);
+                value.set_wrap_mode(gtk::pango::WrapMode::WordChar);
+                value.set_xalign(0.0);
+
+                self.details_grid.attach(&label, 0, row as i32, 1, 1);
+                self.details_grid.attach(&value, 1, row as i32, 1, 1);
+                self.details.borrow_mut().push((property, value));
+            }
+
+            self.extra_key_entry
+                .connect_changed(clone!(@weak obj => move |_| obj.extra_key_changed()));
+            self.extra_value_entry
+                .connect_activate(clone!(@weak obj => move |_| obj.add_extra()));
+            self.add_extra_button
+                .connect_clicked(clone!(@weak obj => move |_| obj.add_extra()));
+
+            self.apply_button
+                .connect_clicked(clone!(@weak obj => move |_| obj.apply()));
+            self.reset_button
+                .connect_clicked(clone!(@weak obj => move |_| obj.reset()));
+
+            obj.reset();
+        }
+        fn dispose(&self) {
+            self.obj().disconnect_files();
+            while let Some(child) = self.obj().first_child() {
+                child.unparent();
+            }
+        }
+    }
+
+    impl WidgetImpl for TagEditor {}
+}
+
+glib::wrapper! {
+    pub struct TagEditor(ObjectSubclass<imp::TagEditor>)
+        @extends Widget;
+}
+
+/// Tag properties shown in the editor
+const FIELDS: &[&str] = &[
+    "title",
+    "artists",
+    "album",
+    "album-artists",
+    "year",
+    "track",
+    "total-tracks",
+    "disc",
+    "total-discs",
+    "genre",
+    "composer",
+];
+
+/// Read-only properties of the audio files shown below the tags
+const DETAILS: &[&str] = &[
+    "format",
+    "codec",
+    "bitrate",
+    "sample-rate",
+    "bit-depth",
+    "channels",
+    "duration",
+    "file-size",
+    "tag-format",
+    "path",
+];
+
+impl TagEditor {
+    pub fn new() -> Self {
+        glib::Object::builder().build()
+    }
+
+    /// Shows the tags of `files`, replacing the previous selection
+    pub fn set_files(&self, files: Vec<TaggableFile>) {
+        let imp = self.imp();
+        self.disconnect_files();
+
+        // Keep the entries in sync with edits made elsewhere, e.g. in the FileColumnView
+        let handlers = files
+            .iter()
+            .map(|taggable_file| {
+                let handler = taggable_file.connect_notify_local(
+                    None,
+                    clone!(@weak self as editor => move |_, _| editor.refresh()),
+                );
+                (taggable_file.clone(), handler)
+            })
+            .collect();
+        imp.notify_handlers.replace(handlers);
+        imp.files.replace(files);
+        imp.shown_extra.replace(None);
+
+        self.reset();
+    }
+
+    /// Writes the edited fields to all selected files.
+    /// Fields the user did not touch keep their per-file values.
+    pub fn apply(&self) {
+        let imp = self.imp();
+        let files = imp.files.borrow().clone();
+
+        // Refresh once at the end instead of for every changed property
+        imp.updating.set(true);
+        // All fields applied at once are undone together
+        let history = files
+            .first()
+            .and_then(|taggable_file| taggable_file.history());
+        let apply_fields = || {
+            for (property, entry) in imp.entries.borrow().iter() {
+                if !imp.touched.borrow().contains(property)
+                    || !is_valid_value(property, &entry.text())
+                {
+                    continue;
+                }
+                for taggable_file in files.iter() {
+                    taggable_file.set_property_text(property, &entry.text());
+                }
+                imp.touched.borrow_mut().remove(property);
+            }
+        };
+        match history {
+            Some(history) => history.group(apply_fields),
+            None => apply_fields(),
+        }
+        imp.updating.set(false);
+
+        self.refresh();
+    }
+
+    /// Drops edits that were not applied yet
+    pub fn reset(&self) {
+        self.imp().touched.borrow_mut().clear();
+        self.refresh();
+    }
+
+    /// Fills the untouched entries from the selected files
+    fn refresh(&self) {
+        let imp = self.imp();
+        if imp.updating.get() {
+            return;
+        }
+        let files = imp.files.borrow();
+        let editable = !files.is_empty() && files.iter().all(TaggableFile::is_editable);
+
+        imp.updating.set(true);
+        for (property, entry) in imp.entries.borrow().iter() {
+            entry.set_sensitive(editable);
+            if imp.touched.borrow().contains(property) {
+                continue;
+            }
+
+            let mut values = files
+                .iter()
+                .map(|taggable_file| taggable_file.property_text(property));
+            let first = values.next();
+            let shared = values.all(|value| Some(&value) == first.as_ref());
+
+            if shared {
+                entry.set_text(&first.unwrap_or_default());
+                entry.set_placeholder_text(None);
+            } else {
+                entry.set_text("");
+                entry.set_placeholder_text(Some(gettext("(multiple values)").as_str()));
+            }
+            entry.remove_css_class("error");
+        }
+        imp.updating.set(false);
+
+        for (property, label) in imp.details.borrow().iter() {
+            let mut values = files
+                .iter()
+                .map(|taggable_file| taggable_file.property_text(property));
+            let first = values.next().unwrap_or_default();
+            if values.all(|value| value == first) {
+                label.set_text(&first);
+            } else {
+                label.set_text(&gettext("(multiple values)"));
+            }
+        }
+
+        imp.apply_button
+            .set_sensitive(!imp.touched.borrow().is_empty());
+        imp.reset_button
+            .set_sensitive(!imp.touched.borrow().is_empty());
+
+        self.refresh_extra();
+    }
+
+    /// The file whose other fields are shown, only a single selected file has one
+    fn extra_file(&self) -> Option<TaggableFile> {
+        match self.imp().files.borrow().as_slice() {
+            [taggable_file] => Some(taggable_file.clone()),
+            _ => None,
+        }
+    }
+
+    /// Shows a row for every value of the other fields of the selected file
+    fn refresh_extra(&self) {
+        let imp = self.imp();
+        let taggable_file = self.extra_file();
+        let extra = taggable_file
+            .as_ref()
+            .map(|taggable_file| taggable_file.extra());
+        imp.extra_label.set_visible(taggable_file.is_none());
+        imp.add_extra_box.set_visible(
+            taggable_file
+                .as_ref()
+                .is_some_and(TaggableFile::is_editable),
+        );
+        self.extra_key_changed();
+        if *imp.shown_extra.borrow() == extra {
+            return;
+        }
+
+        while let Some(row) = imp.extra_listbox.first_child() {
+            imp.extra_listbox.remove(&row);
+        }
+        let fields = extra.clone().unwrap_or_default();
+        imp.extra_listbox
+            .set_visible(fields.values().any(|values| !values.is_empty()));
+        for (key, values) in fields.iter() {
+            for (index, value) in values.iter().enumerate() {
+                imp.extra_listbox.append(&self.extra_row(key, index, value));
+            }
+        }
+        imp.shown_extra.replace(extra);
+    }
+
+    /// Creates a row to change or remove the value at `index` of the field `key`
+    fn extra_row(&self, key: &str, index: usize, value: &str) -> gtk::Box {
+        let row = gtk::Box::new(gtk::Orientation::Horizontal, 6);
+        row.set_margin_top(6);
+        row.set_margin_bottom(6);
+        row.set_margin_start(6);
+        row.set_margin_end(6);
+
+        let key_label = Label::new(Some(key));
+        key_label.set_halign(gtk::Align::Start);
+        key_label.set_width_chars(10);
+        key_label.set_xalign(0.0);
+        key_label.set_selectable(true);
+        key_label.set_ellipsize(gtk::pango::EllipsizeMode::End);
+        key_label.set_tooltip_text(Some(key));
+        key_label.add_css_class("dim-label");
+        row.append(&key_label);
+
+        let value_entry = Entry::new();
+        value_entry.set_hexpand(true);
+        value_entry.set_text(value);
+        let key = key.to_owned();
+        let apply_value = clone!(@weak self as editor, @strong key => move |entry: &Entry| {
+            let value = entry.text().to_string();
+            editor.edit_extra(|extra| {
+                if let Some(old_value) = extra.get_mut(&key).and_then(|values| values.get_mut(index)) {
+                    *old_value = value.clone();
+                }
+            });
+        });
+        value_entry.connect_activate(apply_value.clone());
+        let focus_controller = gtk::EventControllerFocus::new();
+        focus_controller.connect_leave(clone!(@weak value_entry => move |_| {
+            apply_value(&value_entry);
+        }));
+        value_entry.add_controller(&focus_controller);
+        row.append(&value_entry);
+
+        let remove_button = Button::from_icon_name("list-remove-symbolic");
+        remove_button.set_valign(gtk::Align::Center);
+        remove_button.set_tooltip_text(Some(gettext("Remove Field").as_str()));
+        remove_button.add_css_class("flat");
+        remove_button.connect_clicked(clone!(@weak self as editor => move |_| {
+            editor.edit_extra(|extra| {
+                if let Some(values) = extra.get_mut(&key) {
+                    if index < values.len() {
+                        values.remove(index);
+                    }
+                    if values.is_empty() {
+                        extra.remove(&key);
+                    }
+                }
+            });
+            editor.refresh_extra();
+        }));
+        row.append(&remove_button);
+        row
+    }
+
+    /// Changes the other fields of the selected file with `edit`
+    fn edit_extra<F: FnOnce(&mut Fields)>(&self, edit: F) {
+        let imp = self.imp();
+        let taggable_file = match self.extra_file() {
+            Some(taggable_file) => taggable_file,
+            None => return,
+        };
+        let mut extra = taggable_file.extra();
+        edit(&mut extra);
+        if extra == taggable_file.extra() {
+            return;
+        }
+
+        // The rows stay as they are while a value is edited in them
+        imp.updating.set(true);
+        taggable_file.set_extra(extra.clone());
+        imp.updating.set(false);
+        imp.shown_extra.replace(Some(extra));
+    }
+
+    /// Adds the value entered below the rows to the selected file
+    fn add_extra(&self) {
+        let imp = self.imp();
+        let key = imp.extra_key_entry.text().trim().to_owned();
+        let value = imp.extra_value_entry.text().to_string();
+        if !self.is_valid_extra_key(&key) {
+            return;
+        }
+
+        self.edit_extra(|extra| extra.entry(key).or_default().push(value));
+        imp.extra_key_entry.set_text("");
+        imp.extra_value_entry.set_text("");
+        self.refresh_extra();
+    }
+
+    fn extra_key_changed(&self) {
+        let imp = self.imp();
+        let key = imp.extra_key_entry.text();
+        let valid = self.is_valid_extra_key(key.trim());
+        if valid || key.is_empty() {
+            imp.extra_key_entry.remove_css_class("error");
+        } else {
+            imp.extra_key_entry.add_css_class("error");
+        }
+        imp.add_extra_button.set_sensitive(valid);
+    }
+
+    /// Whether `key` can be stored in the tag format of the selected file
+    fn is_valid_extra_key(&self, key: &str) -> bool {
+        self.extra_file()
+            .is_some_and(|taggable_file| is_valid_key(&taggable_file.path(), key))
+    }
+
+    fn field_changed(&self, property: &'static str, entry: &Entry) {
+        let imp = self.imp();
+        if imp.updating.get() {
+            return;
+        }
+
+        imp.touched.borrow_mut().insert(property);
+        if is_valid_value(property, &entry.text()) {
+            entry.remove_css_class("error");
+        } else {
+            entry.add_css_class("error");
+        }
+        imp.apply_button.set_sensitive(true);
+        imp.reset_button.set_sensitive(true);
+    }
+
+    fn disconnect_files(&self) {
+        for (taggable_file, handler) in self.imp().notify_handlers.take() {
+            taggable_file.disconnect(handler);
+        }
+    }
+}
+
+impl Default for TagEditor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
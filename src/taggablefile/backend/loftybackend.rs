@@ -0,0 +1,288 @@
+use crate::taggablefile::backend::TagBackend;
+use crate::taggablefile::error::Result;
+use crate::taggablefile::fields::is_valid_vorbis_key;
+use crate::taggablefile::fileproperties::file_properties;
+use crate::taggablefile::record::{Picture, PictureType, TagRecord};
+use crate::taggablefile::TAG_PROPERTIES;
+use lofty::ape::ApeTag;
+use lofty::{
+    Accessor, FileType, ItemKey, ItemValue, MimeType, Probe, Tag, TagExt, TagItem, TagType,
+    TaggedFileExt,
+};
+use std::path::Path;
+
+/// Extensions of the formats read with lofty
+const EXTENSIONS: &[&str] = &[
+    "ogg", "oga", "opus", "spx", "ape", "wv", "mpc", "mp+", "mpp",
+];
+
+/// Items that are edited as typed fields
+const TYPED_KEYS: &[ItemKey] = &[
+    ItemKey::TrackTitle,
+    ItemKey::TrackArtist,
+    ItemKey::AlbumTitle,
+    ItemKey::AlbumArtist,
+    ItemKey::Composer,
+    ItemKey::Genre,
+    ItemKey::Year,
+    ItemKey::RecordingDate,
+    ItemKey::TrackNumber,
+    ItemKey::TrackTotal,
+    ItemKey::DiscNumber,
+    ItemKey::DiscTotal,
+];
+
+/// Ogg Vorbis, Opus and Speex, and Monkey's Audio, WavPack and Musepack with APEv2, read
+/// and written with lofty. Each file keeps the tag format lofty considers primary for it.
+pub struct LoftyBackend;
+
+impl TagBackend for LoftyBackend {
+    fn supports(&self, path: &Path) -> bool {
+        path.extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| EXTENSIONS.contains(&extension.to_lowercase().as_str()))
+    }
+
+    fn read(&self, path: &Path) -> Result<TagRecord> {
+        let mut tagged_file = Probe::open(path)?.read()?;
+        let mut record = TagRecord {
+            file: file_properties(path, &tagged_file),
+            ..TagRecord::default()
+        };
+        let tag_type = tagged_file
+            .primary_tag()
+            .or_else(|| tagged_file.first_tag())
+            .map(|tag| tag.tag_type());
+        let tag = match tag_type.and_then(|tag_type| tagged_file.tag_mut(tag_type)) {
+            Some(tag) => tag,
+            None => return Ok(record),
+        };
+        take_ape_pictures(tag);
+        let tag = &*tag;
+
+        let text = |key: &ItemKey| tag.get_string(key).map(String::from);
+        // APEv2 separates multiple values of an item with null bytes
+        let list = |key: &ItemKey| {
+            tag.get_strings(key)
+                .flat_map(|value| value.split('\0'))
+                .map(String::from)
+                .collect()
+        };
+        let number = |number: Option<u32>| number.and_then(|number| u16::try_from(number).ok());
+        record.title = tag.title().map(|title| title.into_owned());
+        record.artists = list(&ItemKey::TrackArtist);
+        record.album = tag.album().map(|album| album.into_owned());
+        record.album_artists = list(&ItemKey::AlbumArtist);
+        record.composer = text(&ItemKey::Composer);
+        record.genre = tag.genre().map(|genre| genre.into_owned());
+        record.year = tag.year().and_then(|year| i32::try_from(year).ok());
+        record.track = number(tag.track());
+        record.total_tracks = number(tag.track_total());
+        record.disc = number(tag.disk());
+        record.total_discs = number(tag.disk_total());
+        record.pictures = tag
+            .pictures()
+            .iter()
+            .map(|picture| Picture {
+                picture_type: PictureType::from_number(picture.pic_type().as_u8()),
+                mime_type: picture.mime_type().as_str().to_owned(),
+                description: picture.description().unwrap_or_default().to_owned(),
+                data: picture.data().to_vec(),
+            })
+            .collect();
+
+        for item in tag.items() {
+            if let Some((key, value)) = extra_item(item, tag.tag_type()) {
+                let values = record.extra.entry(key).or_default();
+                values.extend(value.split('\0').map(String::from));
+            }
+        }
+        Ok(record)
+    }
+
+    fn write(&self, path: &Path, record: &TagRecord, saved: &TagRecord) -> Result<()> {
+        let changed = |property: &str| !record.same_field(saved, property);
+        if !TAG_PROPERTIES.iter().any(|property| changed(property)) {
+            return Ok(());
+        }
+        let mut tagged_file = Probe::open(path)?.read()?;
+        let tag_type = tagged_file.primary_tag_type();
+        if tagged_file.primary_tag().is_none() {
+            tagged_file.insert_tag(Tag::new(tag_type));
+        }
+        let tag = match tagged_file.primary_tag_mut() {
+            Some(tag) => tag,
+            None => return Ok(()),
+        };
+        take_ape_pictures(tag);
+
+        if changed("title") {
+            match &record.title {
+                Some(title) => tag.set_title(title.clone()),
+                None => tag.remove_title(),
+            }
+        }
+        if changed("album") {
+            match &record.album {
+                Some(album) => tag.set_album(album.clone()),
+                None => tag.remove_album(),
+            }
+        }
+        if changed("genre") {
+            match &record.genre {
+                Some(genre) => tag.set_genre(genre.clone()),
+                None => tag.remove_genre(),
+            }
+        }
+        if changed("composer") {
+            tag.remove_key(&ItemKey::Composer);
+            if let Some(composer) = &record.composer {
+                tag.insert_text(ItemKey::Composer, composer.clone());
+            }
+        }
+        if changed("year") {
+            match record.year.and_then(|year| u32::try_from(year).ok()) {
+                Some(year) => tag.set_year(year),
+                None => tag.remove_year(),
+            }
+        }
+        if changed("track") {
+            match record.track {
+                Some(track) => tag.set_track(u32::from(track)),
+                None => tag.remove_track(),
+            }
+        }
+        if changed("total-tracks") {
+            match record.total_tracks {
+                Some(total_tracks) => tag.set_track_total(u32::from(total_tracks)),
+                None => tag.remove_track_total(),
+            }
+        }
+        if changed("disc") {
+            match record.disc {
+                Some(disc) => tag.set_disk(u32::from(disc)),
+                None => tag.remove_disk(),
+            }
+        }
+        if changed("total-discs") {
+            match record.total_discs {
+                Some(total_discs) => tag.set_disk_total(u32::from(total_discs)),
+                None => tag.remove_disk_total(),
+            }
+        }
+        for (property, key, list) in [
+            ("artists", ItemKey::TrackArtist, &record.artists),
+            ("album-artists", ItemKey::AlbumArtist, &record.album_artists),
+        ] {
+            if changed(property) {
+                tag.remove_key(&key);
+                let entries = list.iter().filter(|entry| !entry.is_empty()).cloned();
+                push_values(tag, key, entries.collect());
+            }
+        }
+
+        if changed("pictures") {
+            for picture_type in PictureType::ALL {
+                tag.remove_picture_type(lofty::PictureType::from_u8(picture_type.number()));
+            }
+            for picture in record.pictures.iter() {
+                tag.push_picture(lofty::Picture::new_unchecked(
+                    lofty::PictureType::from_u8(picture.picture_type.number()),
+                    MimeType::from_str(&picture.mime_type),
+                    Some(picture.description.clone()).filter(|description| !description.is_empty()),
+                    picture.data.clone(),
+                ));
+            }
+        }
+
+        if changed("extra") {
+            tag.retain(|item| extra_item(item, tag_type).is_none());
+            for (key, values) in record.extra.iter() {
+                push_values(tag, ItemKey::from_key(tag_type, key), values.clone());
+            }
+        }
+
+        // lofty only writes the pictures and the disc of an APEv2 tag right from its own type
+        if tag_type == TagType::Ape {
+            ApeTag::from(tag.clone()).save_to_path(path)?;
+        } else {
+            tag.save_to_path(path)?;
+        }
+        Ok(())
+    }
+
+    fn is_valid_key(&self, path: &Path, key: &str) -> bool {
+        let tag_type = match FileType::from_path(path) {
+            Some(file_type) => file_type.primary_tag_type(),
+            None => return false,
+        };
+        if TYPED_KEYS.contains(&ItemKey::from_key(tag_type, key)) {
+            return false;
+        }
+        match tag_type {
+            TagType::VorbisComments => is_valid_vorbis_key(key),
+            TagType::Ape => is_valid_ape_key(key),
+            _ => false,
+        }
+    }
+}
+
+/// APEv2 item keys are 2 to 255 characters of printable ASCII, other than the ones
+/// that would be mistaken for other tags
+fn is_valid_ape_key(key: &str) -> bool {
+    (2..=255).contains(&key.len())
+        && key.bytes().all(|byte| (0x20..=0x7e).contains(&byte))
+        && !["ID3", "TAG", "OGGS", "MP+"].contains(&key.to_ascii_uppercase().as_str())
+}
+
+/// Adds an item with each of `values` to `tag`, or a single one with all of them for
+/// APEv2, which keeps one item per key
+fn push_values(tag: &mut Tag, key: ItemKey, values: Vec<String>) {
+    if values.is_empty() {
+        return;
+    }
+    if tag.tag_type() == TagType::Ape {
+        tag.push(TagItem::new(key, ItemValue::Text(values.join("\0"))));
+    } else {
+        for value in values {
+            tag.push(TagItem::new(key.clone(), ItemValue::Text(value)));
+        }
+    }
+}
+
+/// Moves the cover art of an APEv2 tag, which lofty reads as binary items, to the
+/// pictures of `tag`
+fn take_ape_pictures(tag: &mut Tag) {
+    if tag.tag_type() != TagType::Ape {
+        return;
+    }
+    let mut pictures = vec![];
+    tag.retain(|item| match (item.key(), item.value()) {
+        (ItemKey::Unknown(key), ItemValue::Binary(data)) => {
+            match lofty::Picture::from_ape_bytes(key, data) {
+                Ok(picture) => {
+                    pictures.push(picture);
+                    false
+                }
+                Err(_) => true,
+            }
+        }
+        _ => true,
+    });
+    for picture in pictures {
+        tag.push_picture(picture);
+    }
+}
+
+/// The native key and value of an item shown as other field, `None` for typed and
+/// binary items
+fn extra_item(item: &TagItem, tag_type: TagType) -> Option<(String, String)> {
+    if TYPED_KEYS.contains(item.key()) {
+        return None;
+    }
+    let key = item.key().map_key(tag_type, true)?.to_owned();
+    match item.value() {
+        ItemValue::Text(text) | ItemValue::Locator(text) => Some((key, text.clone())),
+        ItemValue::Binary(_) => None,
+    }
+}
@@ -0,0 +1,39 @@
+use crate::taggablefile::error::Result;
+use crate::taggablefile::record::TagRecord;
+use std::path::Path;
+
+mod loftybackend;
+mod nativebackend;
+#[cfg(test)]
+mod tests;
+
+pub use loftybackend::LoftyBackend;
+pub use nativebackend::NativeBackend;
+
+/// Reads and writes the tags of the file formats a tag library supports. Files of other
+/// formats are still listed, but can't be edited.
+pub trait TagBackend: Sync {
+    /// Whether the file at `path` is in one of the formats of this backend
+    fn supports(&self, path: &Path) -> bool;
+
+    /// Reads the tag and the audio properties of the file at `path`
+    fn read(&self, path: &Path) -> Result<TagRecord>;
+
+    /// Writes the fields of `record` that differ from `saved`, the tag as it is on disk,
+    /// to the file at `path`
+    fn write(&self, path: &Path, record: &TagRecord, saved: &TagRecord) -> Result<()>;
+
+    /// Whether `key` can be written as other field to the tag of the file at `path`
+    fn is_valid_key(&self, path: &Path, key: &str) -> bool;
+}
+
+/// The available backends, a file is read and written by the first one supporting it
+static BACKENDS: &[&dyn TagBackend] = &[&NativeBackend, &LoftyBackend];
+
+/// The backend for the file at `path`, `None` if its format is not supported
+pub fn backend_for(path: &Path) -> Option<&'static dyn TagBackend> {
+    BACKENDS
+        .iter()
+        .copied()
+        .find(|backend| backend.supports(path))
+}
@@ -0,0 +1,443 @@
+use crate::taggablefile::backend::{LoftyBackend, TagBackend};
+use crate::taggablefile::error::Result;
+use crate::taggablefile::fields::{
+    is_valid_native_key, read_fields, set_flac_fields, set_id3_fields, set_mp4_fields,
+};
+use crate::taggablefile::fileproperties::read_file_properties;
+use crate::taggablefile::native::{native_tag, read_id3, write_id3, NativeTag};
+use crate::taggablefile::pictures::{
+    check_pictures, read_id3_tag, read_pictures, set_flac_pictures, set_id3_pictures,
+    set_mp4_pictures,
+};
+use crate::taggablefile::record::{FileProperties, TagRecord};
+use crate::taggablefile::TAG_PROPERTIES;
+use id3::TagLike;
+use metaflac::block::VorbisComment;
+use std::path::Path;
+
+/// Properties of the typed fields the `set_*_common_fields` functions write
+const COMMON_PROPERTIES: &[&str] = &[
+    "title",
+    "artists",
+    "album",
+    "album-artists",
+    "composer",
+    "genre",
+    "year",
+    "track",
+    "total-tracks",
+    "disc",
+    "total-discs",
+];
+
+/// MP3, FLAC and MP4 files and the ID3v2 tags of WAV and AIFF files, read and written
+/// with the library of their native tag format
+pub struct NativeBackend;
+
+impl TagBackend for NativeBackend {
+    fn supports(&self, path: &Path) -> bool {
+        native_tag(path).is_some()
+    }
+
+    fn read(&self, path: &Path) -> Result<TagRecord> {
+        let record = match native_tag(path) {
+            Some(NativeTag::Id3) => match read_id3(path) {
+                Ok(tag) => id3_record(&tag),
+                // Until an ID3v2 tag is added, the ID3v1 tag, RIFF INFO or AIFF text chunks
+                // lofty reads are shown
+                Err(err) if matches!(err.kind, id3::ErrorKind::NoTag) => {
+                    return LoftyBackend.read(path)
+                }
+                Err(err) => return Err(err.into()),
+            },
+            Some(NativeTag::Flac) => metaflac::Tag::read_from_path(path)?
+                .vorbis_comments()
+                .map(vorbis_record)
+                .unwrap_or_default(),
+            Some(NativeTag::Mp4) => mp4_record(&mp4ameta::Tag::read_from_path(path)?),
+            None => TagRecord::default(),
+        };
+        Ok(TagRecord {
+            file: file_properties(path),
+            pictures: read_pictures(path)?,
+            extra: read_fields(path)?,
+            ..record
+        })
+    }
+
+    /// Only the parts of the tag that changed are written again, the rest of the file and
+    /// the ID3v2 version stay as they are. All changes are made to the tag in memory,
+    /// which is written once, so a failure leaves the file as it was.
+    fn write(&self, path: &Path, record: &TagRecord, saved: &TagRecord) -> Result<()> {
+        // A new ID3v2 tag gets all fields, including those shown from another tag
+        let untagged = TagRecord::default();
+        let saved = match native_tag(path) {
+            Some(NativeTag::Id3) if !has_id3_tag(path) => &untagged,
+            _ => saved,
+        };
+        let changed = |property: &str| !record.same_field(saved, property);
+        if !TAG_PROPERTIES.iter().any(|property| changed(property)) {
+            return Ok(());
+        }
+        // Pictures the file can't store must not cost it its other pictures
+        if changed("pictures") {
+            check_pictures(path, &record.pictures)?;
+        }
+        let common_changed = COMMON_PROPERTIES.iter().any(|property| changed(property));
+
+        match native_tag(path) {
+            Some(NativeTag::Id3) => {
+                let (mut tag, version) = match read_id3_tag(path) {
+                    Ok(tag) => {
+                        let version = tag.version();
+                        (tag, version)
+                    }
+                    Err(err) if matches!(err.kind, id3::ErrorKind::NoTag) => {
+                        (id3::Tag::new(), id3::Version::Id3v24)
+                    }
+                    Err(err) => return Err(err.into()),
+                };
+                if common_changed {
+                    set_id3_common_fields(&mut tag, version, record, saved);
+                }
+                if changed("pictures") {
+                    set_id3_pictures(&mut tag, &record.pictures);
+                }
+                if changed("extra") {
+                    set_id3_fields(&mut tag, &record.extra, path);
+                }
+                write_id3(&tag, path, version)?;
+            }
+            Some(NativeTag::Flac) => {
+                let mut tag = metaflac::Tag::read_from_path(path)?;
+                if common_changed {
+                    set_flac_common_fields(&mut tag, record, saved);
+                }
+                if changed("pictures") {
+                    set_flac_pictures(&mut tag, &record.pictures);
+                }
+                if changed("extra") {
+                    set_flac_fields(&mut tag, &record.extra, path);
+                }
+                tag.write_to_path(path)?;
+            }
+            Some(NativeTag::Mp4) => {
+                let mut tag = mp4ameta::Tag::read_from_path(path)?;
+                if common_changed {
+                    set_mp4_common_fields(&mut tag, record, saved);
+                }
+                if changed("pictures") {
+                    set_mp4_pictures(&mut tag, &record.pictures);
+                }
+                if changed("extra") {
+                    set_mp4_fields(&mut tag, &record.extra, path);
+                }
+                tag.write_to_path(path)?;
+            }
+            None => (),
+        }
+        Ok(())
+    }
+
+    fn is_valid_key(&self, path: &Path, key: &str) -> bool {
+        is_valid_native_key(path, key)
+    }
+}
+
+/// Whether the file at `path` has an ID3v2 tag, errors count as tag to not hide them
+fn has_id3_tag(path: &Path) -> bool {
+    !matches!(read_id3(path), Err(err) if matches!(err.kind, id3::ErrorKind::NoTag))
+}
+
+/// Reads the audio properties from the file headers, with the average bitrate of the
+/// whole file for files without a stream bitrate
+fn file_properties(path: &Path) -> FileProperties {
+    let mut properties = read_file_properties(path);
+    // Without the stream bitrate, the average over the whole file is close enough
+    if let (None, Some(file_size), Some(duration)) = (
+        properties.bitrate,
+        properties.file_size,
+        properties.duration,
+    ) {
+        if !duration.is_zero() {
+            properties.bitrate =
+                Some((file_size as f64 * 8.0 / duration.as_secs_f64() / 1000.0).round() as u32);
+        }
+    }
+    properties
+}
+
+/// The number and the total of a "3/12" pair, either may be missing
+fn number_pair(text: &str) -> (Option<u16>, Option<u16>) {
+    let mut split = text.splitn(2, &['/', '\0'][..]);
+    let mut number = || split.next().and_then(|number| number.trim().parse().ok());
+    (number(), number())
+}
+
+/// The year of a date like "1999" or "1999-05-03"
+fn date_year(date: &str) -> Option<i32> {
+    date.trim().get(..4).and_then(|year| year.parse().ok())
+}
+
+/// The values of `list` without empty ones, which tags don't store
+fn non_empty(list: &[String]) -> Vec<String> {
+    list.iter()
+        .filter(|entry| !entry.is_empty())
+        .cloned()
+        .collect()
+}
+
+fn id3_record(tag: &id3::Tag) -> TagRecord {
+    let text = |id: &str| tag.get(id).and_then(|frame| frame.content().text());
+    // ID3v2.4 separates multiple values of a text frame with null bytes
+    let list = |id: &str| {
+        text(id)
+            .map(|text| text.split('\0').map(String::from).collect())
+            .unwrap_or_default()
+    };
+    let (track, total_tracks) = text("TRCK").map(number_pair).unwrap_or_default();
+    let (disc, total_discs) = text("TPOS").map(number_pair).unwrap_or_default();
+    TagRecord {
+        title: text("TIT2").map(String::from),
+        artists: list("TPE1"),
+        album: text("TALB").map(String::from),
+        album_artists: list("TPE2"),
+        composer: text("TCOM").map(String::from),
+        genre: text("TCON").map(String::from),
+        year: text("TDRC").or_else(|| text("TYER")).and_then(date_year),
+        track,
+        total_tracks,
+        disc,
+        total_discs,
+        ..TagRecord::default()
+    }
+}
+
+fn vorbis_record(comments: &VorbisComment) -> TagRecord {
+    let list = |keys: &[&str]| {
+        keys.iter()
+            .find_map(|key| comments.get(key))
+            .cloned()
+            .unwrap_or_default()
+    };
+    let text = |keys: &[&str]| list(keys).into_iter().next();
+    let number = |keys: &[&str]| text(keys).and_then(|text| number_pair(&text).0);
+    let (track, disc) = (text(&["TRACKNUMBER"]), text(&["DISCNUMBER"]));
+    TagRecord {
+        title: text(&["TITLE"]),
+        artists: list(&["ARTIST"]),
+        album: text(&["ALBUM"]),
+        album_artists: list(&["ALBUMARTIST"]),
+        composer: text(&["COMPOSER"]),
+        genre: text(&["GENRE"]),
+        year: text(&["DATE", "YEAR"]).and_then(|date| date_year(&date)),
+        track: number(&["TRACKNUMBER"]),
+        // The total may also follow the number, as in "3/12"
+        total_tracks: number(&["TRACKTOTAL", "TOTALTRACKS"])
+            .or_else(|| track.and_then(|track| number_pair(&track).1)),
+        disc: number(&["DISCNUMBER"]),
+        total_discs: number(&["DISCTOTAL", "TOTALDISCS"])
+            .or_else(|| disc.and_then(|disc| number_pair(&disc).1)),
+        ..TagRecord::default()
+    }
+}
+
+fn mp4_record(tag: &mp4ameta::Tag) -> TagRecord {
+    TagRecord {
+        title: tag.title().map(String::from),
+        artists: tag.artists().map(String::from).collect(),
+        album: tag.album().map(String::from),
+        album_artists: tag.album_artists().map(String::from).collect(),
+        composer: tag.composer().map(String::from),
+        genre: tag.genre().map(String::from),
+        year: tag.year().and_then(date_year),
+        track: tag.track_number(),
+        total_tracks: tag.total_tracks(),
+        disc: tag.disc_number(),
+        total_discs: tag.total_discs(),
+        ..TagRecord::default()
+    }
+}
+
+/// The value of an optional text field as list, empty without value
+fn text_values(text: &Option<String>) -> Vec<String> {
+    text.iter().cloned().collect()
+}
+
+/// The value of an optional number field as list, empty without value
+fn number_values(number: Option<u16>) -> Vec<String> {
+    number.iter().map(u16::to_string).collect()
+}
+
+/// Sets the typed fields of `record` that differ from `saved` in an ID3v2 tag of
+/// `version`. Fields without a value are removed.
+fn set_id3_common_fields(
+    tag: &mut id3::Tag,
+    version: id3::Version,
+    record: &TagRecord,
+    saved: &TagRecord,
+) {
+    let changed = |property: &str| !record.same_field(saved, property);
+    // Track and disc frames store the number and the total as "3/12". A total
+    // without number has no place there, readers reject "/12".
+    let pair = |value: Option<u16>, total: Option<u16>| match (value, total) {
+        (Some(value), Some(total)) => vec![format!("{}/{}", value, total)],
+        (value, _) => number_values(value),
+    };
+    for (properties, id, values) in [
+        (&["title"][..], "TIT2", text_values(&record.title)),
+        (&["artists"], "TPE1", non_empty(&record.artists)),
+        (&["album"], "TALB", text_values(&record.album)),
+        (&["album-artists"], "TPE2", non_empty(&record.album_artists)),
+        (&["composer"], "TCOM", text_values(&record.composer)),
+        (&["genre"], "TCON", text_values(&record.genre)),
+        (
+            &["track", "total-tracks"],
+            "TRCK",
+            pair(record.track, record.total_tracks),
+        ),
+        (
+            &["disc", "total-discs"],
+            "TPOS",
+            pair(record.disc, record.total_discs),
+        ),
+    ] {
+        if properties.iter().any(|property| changed(property)) {
+            tag.remove(id);
+            if !values.is_empty() {
+                // ID3v2.4 separates multiple values of a text frame with null bytes
+                tag.set_text(id, values.join("\0"));
+            }
+        }
+    }
+    if changed("year") {
+        // A full recording date keeps its month and day
+        let date = tag.date_recorded();
+        tag.remove_year();
+        tag.remove_date_recorded();
+        match (record.year, date) {
+            (Some(year), Some(date)) => tag.set_date_recorded(id3::Timestamp { year, ..date }),
+            (Some(year), None) if version == id3::Version::Id3v24 => {
+                tag.set_date_recorded(id3::Timestamp {
+                    year,
+                    month: None,
+                    day: None,
+                    hour: None,
+                    minute: None,
+                    second: None,
+                })
+            }
+            (Some(year), None) => tag.set_year(year),
+            (None, _) => (),
+        }
+    }
+}
+
+/// Sets the typed fields of `record` that differ from `saved` in the Vorbis comments of a
+/// FLAC tag, see `set_id3_common_fields`
+fn set_flac_common_fields(tag: &mut metaflac::Tag, record: &TagRecord, saved: &TagRecord) {
+    let changed = |property: &str| !record.same_field(saved, property);
+    let comments = tag.vorbis_comments_mut();
+    // A full date keeps its month and day
+    let date = comments
+        .get("DATE")
+        .and_then(|dates| dates.first())
+        .filter(|date| date_year(date).is_some())
+        .map(|date| date.trim()[4..].to_owned())
+        .unwrap_or_default();
+    // The first key is written, the others are alternatives that are removed
+    for (property, keys, values) in [
+        ("title", &["TITLE"][..], text_values(&record.title)),
+        ("artists", &["ARTIST"], non_empty(&record.artists)),
+        ("album", &["ALBUM"], text_values(&record.album)),
+        (
+            "album-artists",
+            &["ALBUMARTIST"],
+            non_empty(&record.album_artists),
+        ),
+        ("composer", &["COMPOSER"], text_values(&record.composer)),
+        ("genre", &["GENRE"], text_values(&record.genre)),
+        (
+            "year",
+            &["DATE", "YEAR"],
+            record
+                .year
+                .iter()
+                .map(|year| format!("{:04}{}", year, date))
+                .collect(),
+        ),
+        ("track", &["TRACKNUMBER"], number_values(record.track)),
+        (
+            "total-tracks",
+            &["TRACKTOTAL", "TOTALTRACKS"],
+            number_values(record.total_tracks),
+        ),
+        ("disc", &["DISCNUMBER"], number_values(record.disc)),
+        (
+            "total-discs",
+            &["DISCTOTAL", "TOTALDISCS"],
+            number_values(record.total_discs),
+        ),
+    ] {
+        if changed(property) {
+            for key in keys {
+                comments.remove(key);
+            }
+            if !values.is_empty() {
+                comments.set(keys[0], values);
+            }
+        }
+    }
+}
+
+/// Sets the typed fields of `record` that differ from `saved` in an MP4 tag, see
+/// `set_id3_common_fields`
+fn set_mp4_common_fields(tag: &mut mp4ameta::Tag, record: &TagRecord, saved: &TagRecord) {
+    let changed = |property: &str| !record.same_field(saved, property);
+    if changed("title") {
+        match &record.title {
+            Some(title) => tag.set_title(title.clone()),
+            None => tag.remove_title(),
+        }
+    }
+    if changed("artists") {
+        tag.set_artists(non_empty(&record.artists));
+    }
+    if changed("album") {
+        match &record.album {
+            Some(album) => tag.set_album(album.clone()),
+            None => tag.remove_album(),
+        }
+    }
+    if changed("album-artists") {
+        tag.set_album_artists(non_empty(&record.album_artists));
+    }
+    if changed("composer") {
+        tag.set_composers(text_values(&record.composer));
+    }
+    if changed("genre") {
+        match &record.genre {
+            Some(genre) => tag.set_genre(genre.clone()),
+            None => tag.remove_genres(),
+        }
+    }
+    if changed("year") {
+        match record.year {
+            Some(year) => tag.set_year(format!("{:04}", year)),
+            None => tag.remove_year(),
+        }
+    }
+    // Track and disc atoms store both numbers, 0 if one is missing
+    if changed("track") || changed("total-tracks") {
+        match (record.track, record.total_tracks) {
+            (None, None) => tag.remove_track(),
+            (track, total) => tag.set_track(track.unwrap_or(0), total.unwrap_or(0)),
+        }
+    }
+    if changed("disc") || changed("total-discs") {
+        match (record.disc, record.total_discs) {
+            (None, None) => tag.remove_disc(),
+            (disc, total) => tag.set_disc(disc.unwrap_or(0), total.unwrap_or(0)),
+        }
+    }
+}
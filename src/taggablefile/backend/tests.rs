@@ -0,0 +1,266 @@
+//! Round trips through the backends with the files in tests/fixtures
+
+use super::backend_for;
+use crate::taggablefile::native::read_id3;
+use crate::taggablefile::record::{FileProperties, Picture, PictureType, TagRecord};
+use id3::TagLike;
+use lofty::TaggedFileExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::{env, fs, process};
+
+/// One file per tag format and library, see tests/fixtures/README.md
+const FIXTURES: &[&str] = &[
+    "id3v23.mp3",
+    "id3v24.mp3",
+    "padding-seektable.flac",
+    "freeform.m4a",
+    "multivalue.ogg",
+    "riff-info-id3.wav",
+    "id3.aiff",
+    "apev2.ape",
+];
+
+/// Number of fixture copies made, to give each test its own files
+static COPIES: AtomicUsize = AtomicUsize::new(0);
+
+/// A copy of a fixture in the temporary folder that is removed again when dropped
+struct Fixture {
+    path: PathBuf,
+}
+
+impl Fixture {
+    fn copy(name: &str) -> Self {
+        let fixture = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures")
+            .join(name);
+        let path = env::temp_dir().join(format!(
+            "easier-tag-{}-{}-{}",
+            process::id(),
+            COPIES.fetch_add(1, Ordering::Relaxed),
+            name
+        ));
+        fs::copy(fixture, &path).unwrap();
+        Self { path }
+    }
+
+    fn read(&self) -> TagRecord {
+        backend_for(&self.path)
+            .unwrap()
+            .read(&self.path)
+            .unwrap_or_else(|err| panic!("reading {}: {}", self.path.display(), err))
+    }
+
+    fn write(&self, record: &TagRecord, saved: &TagRecord) {
+        backend_for(&self.path)
+            .unwrap()
+            .write(&self.path, record, saved)
+            .unwrap_or_else(|err| panic!("writing {}: {}", self.path.display(), err))
+    }
+
+    /// The tag as the library of its format lists it, sorted, to compare the parts of the
+    /// tag `TagRecord` doesn't show
+    fn native_frames(&self) -> Vec<String> {
+        let mut frames = match self
+            .path
+            .extension()
+            .and_then(|extension| extension.to_str())
+        {
+            Some("mp3" | "wav" | "aiff") => {
+                let tag = read_id3(&self.path).unwrap();
+                let mut frames = vec![format!("{:?}", tag.version())];
+                frames.extend(tag.frames().map(|frame| format!("{:?}", frame)));
+                frames
+            }
+            Some("flac") => {
+                let tag = metaflac::Tag::read_from_path(&self.path).unwrap();
+                tag.blocks()
+                    .map(|block| match block {
+                        // The padding shrinks and grows with the other blocks
+                        metaflac::Block::Padding(_) => String::from("Padding"),
+                        metaflac::Block::VorbisComment(comments) => {
+                            let mut comments = comments.comments.iter().collect::<Vec<_>>();
+                            comments.sort();
+                            format!("VorbisComment {:?}", comments)
+                        }
+                        block => format!("{:?}", block),
+                    })
+                    .collect()
+            }
+            Some("m4a") => {
+                let tag = mp4ameta::Tag::read_from_path(&self.path).unwrap();
+                tag.data()
+                    .map(|(ident, data)| format!("{:?} {:?}", ident, data))
+                    .collect()
+            }
+            _ => {
+                let tagged_file = lofty::read_from_path(&self.path).unwrap();
+                let mut frames = vec![];
+                for tag in tagged_file.tags() {
+                    frames.extend(
+                        tag.items()
+                            .map(|item| format!("{:?} {:?}", tag.tag_type(), item)),
+                    );
+                    frames.extend(
+                        tag.pictures()
+                            .iter()
+                            .map(|picture| format!("{:?} {:?}", tag.tag_type(), picture)),
+                    );
+                }
+                frames
+            }
+        };
+        frames.sort();
+        frames
+    }
+}
+
+impl Drop for Fixture {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// The tag without the audio properties, some of which change with the tag size
+fn tag_only(record: &TagRecord) -> TagRecord {
+    TagRecord {
+        file: FileProperties::default(),
+        ..record.clone()
+    }
+}
+
+#[test]
+fn reads_fixtures() {
+    for name in FIXTURES {
+        let record = Fixture::copy(name).read();
+        assert_eq!(record.title.as_deref(), Some("Title"), "{}", name);
+        assert_eq!(record.album.as_deref(), Some("Album"), "{}", name);
+        assert_eq!(record.year, Some(1999), "{}", name);
+        assert_eq!(record.track, Some(3), "{}", name);
+        assert!(record.file.duration.is_some(), "{}", name);
+        assert!(record.file.sample_rate.is_some(), "{}", name);
+        assert!(!record.pictures.is_empty(), "{}", name);
+        assert!(!record.extra.is_empty(), "{}", name);
+    }
+}
+
+#[test]
+fn unchanged_tags_are_not_written() {
+    for name in FIXTURES {
+        let fixture = Fixture::copy(name);
+        let bytes = fs::read(&fixture.path).unwrap();
+        let saved = fixture.read();
+        fixture.write(&saved, &saved);
+        assert!(fs::read(&fixture.path).unwrap() == bytes, "{}", name);
+    }
+}
+
+#[test]
+fn rewriting_all_fields_keeps_the_tag() {
+    for name in FIXTURES {
+        let fixture = Fixture::copy(name);
+        let frames = fixture.native_frames();
+        let saved = fixture.read();
+        // Every field differs from an empty tag, so all of them are written
+        fixture.write(&saved, &TagRecord::default());
+        assert_eq!(tag_only(&fixture.read()), tag_only(&saved), "{}", name);
+        assert_eq!(fixture.native_frames(), frames, "{}", name);
+    }
+}
+
+#[test]
+fn editing_one_field_keeps_the_others() {
+    for name in FIXTURES {
+        let fixture = Fixture::copy(name);
+        let frames = fixture.native_frames();
+        let saved = fixture.read();
+
+        let mut record = saved.clone();
+        record.title = Some(String::from("Another title"));
+        fixture.write(&record, &saved);
+        let read = fixture.read();
+        assert_eq!(tag_only(&read), tag_only(&record), "{}", name);
+        // Other tags of the file, like RIFF INFO next to ID3v2, stay
+        assert_eq!(read.file.tag_format, saved.file.tag_format, "{}", name);
+
+        // Changing it back restores every frame of the tag
+        fixture.write(&saved, &record);
+        assert_eq!(fixture.native_frames(), frames, "{}", name);
+    }
+}
+
+#[test]
+fn edits_every_field() {
+    for name in FIXTURES {
+        let fixture = Fixture::copy(name);
+        let saved = fixture.read();
+
+        let mut record = saved.clone();
+        record.set_text("title", "Títle ✓").unwrap();
+        record.set_text("artists", "First, Second").unwrap();
+        record.set_text("album", "").unwrap();
+        record.set_text("album-artists", "Various").unwrap();
+        record.set_text("composer", "Someone Else").unwrap();
+        record.set_text("genre", "").unwrap();
+        record.set_text("year", "2001").unwrap();
+        record.set_text("track", "7").unwrap();
+        record.set_text("total-tracks", "9").unwrap();
+        record.set_text("disc", "2").unwrap();
+        record.set_text("total-discs", "3").unwrap();
+        let mut cover = record.front_cover().unwrap().clone();
+        cover.picture_type = PictureType::FrontCover;
+        record.pictures = vec![
+            cover.clone(),
+            Picture {
+                picture_type: PictureType::BackCover,
+                ..cover
+            },
+        ];
+        fixture.write(&record, &saved);
+
+        let mut read = fixture.read();
+        // MP4 has no picture types, only the first picture is taken as front cover
+        if name.ends_with(".m4a") {
+            read.pictures[1].picture_type = PictureType::BackCover;
+        }
+        assert_eq!(tag_only(&read), tag_only(&record), "{}", name);
+    }
+}
+
+#[test]
+fn adds_other_fields_with_valid_keys() {
+    for (name, key, typed_key) in [
+        ("id3v23.mp3", "TXXX:MOOD", "TIT2"),
+        ("padding-seektable.flac", "MOOD", "TITLE"),
+        ("freeform.m4a", "----:com.apple.iTunes:MOOD", "\u{a9}nam"),
+        ("multivalue.ogg", "MOOD", "TITLE"),
+        ("riff-info-id3.wav", "TXXX:MOOD", "TIT2"),
+        ("apev2.ape", "Mood", "Title"),
+    ] {
+        let fixture = Fixture::copy(name);
+        let backend = backend_for(&fixture.path).unwrap();
+        assert!(backend.is_valid_key(&fixture.path, key), "{}", name);
+        assert!(!backend.is_valid_key(&fixture.path, typed_key), "{}", name);
+        assert!(!backend.is_valid_key(&fixture.path, ""), "{}", name);
+
+        let saved = fixture.read();
+        let mut record = saved.clone();
+        record
+            .extra
+            .insert(String::from(key), vec![String::from("Calm")]);
+        fixture.write(&record, &saved);
+        assert_eq!(fixture.read().extra, record.extra, "{}", name);
+    }
+}
+
+#[test]
+fn id3_totals_without_number_are_left_out() {
+    let fixture = Fixture::copy("id3v24.mp3");
+    let saved = fixture.read();
+    let mut record = saved.clone();
+    record.set_text("track", "").unwrap();
+    fixture.write(&record, &saved);
+    let tag = read_id3(&fixture.path).unwrap();
+    assert!(tag.get("TRCK").is_none());
+    assert_eq!(fixture.read().disc, Some(1));
+}
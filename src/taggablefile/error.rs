@@ -0,0 +1,76 @@
+use std::{fmt, io};
+
+/// Why the tag of a file could not be read or written
+#[derive(Debug)]
+pub enum TagError {
+    Id3(id3::Error),
+    Flac(metaflac::Error),
+    Mp4(mp4ameta::Error),
+    Lofty(lofty::LoftyError),
+    Io(io::Error),
+    // A picture with this MIME type can't be stored in the tag format of the file
+    UnsupportedPicture(String),
+    // No backend supports the format of the file
+    Unsupported,
+}
+
+pub type Result<T> = std::result::Result<T, TagError>;
+
+impl fmt::Display for TagError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TagError::Id3(err) => err.fmt(f),
+            TagError::Flac(err) => err.fmt(f),
+            TagError::Mp4(err) => err.fmt(f),
+            TagError::Lofty(err) => err.fmt(f),
+            TagError::Io(err) => err.fmt(f),
+            TagError::UnsupportedPicture(mime_type) => {
+                write!(f, "{} pictures can't be stored in this format", mime_type)
+            }
+            TagError::Unsupported => write!(f, "unsupported file format"),
+        }
+    }
+}
+
+impl std::error::Error for TagError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TagError::Id3(err) => Some(err),
+            TagError::Flac(err) => Some(err),
+            TagError::Mp4(err) => Some(err),
+            TagError::Lofty(err) => Some(err),
+            TagError::Io(err) => Some(err),
+            TagError::UnsupportedPicture(_) | TagError::Unsupported => None,
+        }
+    }
+}
+
+impl From<id3::Error> for TagError {
+    fn from(err: id3::Error) -> Self {
+        TagError::Id3(err)
+    }
+}
+
+impl From<metaflac::Error> for TagError {
+    fn from(err: metaflac::Error) -> Self {
+        TagError::Flac(err)
+    }
+}
+
+impl From<mp4ameta::Error> for TagError {
+    fn from(err: mp4ameta::Error) -> Self {
+        TagError::Mp4(err)
+    }
+}
+
+impl From<lofty::LoftyError> for TagError {
+    fn from(err: lofty::LoftyError) -> Self {
+        TagError::Lofty(err)
+    }
+}
+
+impl From<io::Error> for TagError {
+    fn from(err: io::Error) -> Self {
+        TagError::Io(err)
+    }
+}
@@ -0,0 +1,433 @@
+use crate::taggablefile::backend::backend_for;
+use crate::taggablefile::error::Result;
+use crate::taggablefile::native::{native_tag, read_id3, NativeTag};
+use id3::frame::{Comment, Content, ExtendedLink, ExtendedText, Lyrics};
+use id3::TagLike;
+use log::warn;
+use mp4ameta::{Data, DataIdent, Fourcc};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Fields of a tag by their native key, see `TagRecord::extra`
+pub type Fields = BTreeMap<String, Vec<String>>;
+
+/// ID3v2 frames that are edited as typed fields or pictures
+const ID3_TYPED_FRAMES: &[&str] = &[
+    "TIT2", "TPE1", "TALB", "TPE2", "TCOM", "TCON", "TYER", "TDRC", "TRCK", "TPOS", "APIC",
+];
+
+/// Vorbis comments that are edited as typed fields or pictures
+const VORBIS_TYPED_KEYS: &[&str] = &[
+    "TITLE",
+    "ARTIST",
+    "ALBUM",
+    "ALBUMARTIST",
+    "COMPOSER",
+    "GENRE",
+    "DATE",
+    "YEAR",
+    "TRACKNUMBER",
+    "TRACKTOTAL",
+    "TOTALTRACKS",
+    "DISCNUMBER",
+    "DISCTOTAL",
+    "TOTALDISCS",
+    "METADATA_BLOCK_PICTURE",
+];
+
+/// MP4 atoms that are edited as typed fields or pictures
+const MP4_TYPED_ATOMS: &[&[u8; 4]] = &[
+    b"\xa9nam", b"\xa9ART", b"\xa9alb", b"aART", b"\xa9wrt", b"\xa9gen", b"gnre", b"\xa9day",
+    b"trkn", b"disk", b"covr",
+];
+
+/// Prefix of the keys of MP4 freeform atoms, followed by their mean and name
+const MP4_FREEFORM_PREFIX: &str = "----:";
+
+/// Reads every text field of the native tag that has no typed counterpart in
+/// `TagRecord`. ID3v2 user defined frames, comments and lyrics are keyed by frame id
+/// and description, e.g. "TXXX:CATALOGNUMBER", MP4 freeform atoms by mean and name,
+/// e.g. "----:com.apple.iTunes:ISRC".
+pub fn read_fields(path: &Path) -> Result<Fields> {
+    let mut fields = Fields::new();
+    match native_tag(path) {
+        Some(NativeTag::Id3) => {
+            let tag = match read_id3(path) {
+                Ok(tag) => tag,
+                Err(err) if matches!(err.kind, id3::ErrorKind::NoTag) => return Ok(fields),
+                Err(err) => return Err(err.into()),
+            };
+            for frame in tag.frames() {
+                if let Some((key, values)) = id3_field(frame) {
+                    fields.entry(key).or_default().extend(values);
+                }
+            }
+        }
+        Some(NativeTag::Flac) => {
+            let tag = metaflac::Tag::read_from_path(path)?;
+            if let Some(comments) = tag.vorbis_comments() {
+                for (key, values) in comments.comments.iter() {
+                    if is_listed_vorbis_key(key) {
+                        fields
+                            .entry(key.clone())
+                            .or_default()
+                            .extend(values.iter().cloned());
+                    }
+                }
+            }
+        }
+        Some(NativeTag::Mp4) => {
+            let tag = mp4ameta::Tag::read_from_path(path)?;
+            for (ident, data) in tag.data() {
+                if let (Some(key), Some(value)) = (mp4_key(ident), mp4_text(data)) {
+                    fields.entry(key).or_default().push(value);
+                }
+            }
+        }
+        None => (),
+    }
+    Ok(fields)
+}
+
+/// Replaces the frames `read_fields` lists in an ID3v2 tag with `fields`. Frames that
+/// can't be shown as text are kept as they are, fields with invalid keys are left out
+/// with a warning about the file at `path`.
+pub fn set_id3_fields(tag: &mut id3::Tag, fields: &Fields, path: &Path) {
+    // Comments and lyrics keep their languages, the values of a key are in the
+    // order of their frames
+    let mut languages = BTreeMap::<String, Vec<String>>::new();
+    for frame in tag.frames() {
+        let (key, lang) = match frame.content() {
+            Content::Comment(comment) => (format!("COMM:{}", comment.description), &comment.lang),
+            Content::Lyrics(lyrics) => (format!("USLT:{}", lyrics.description), &lyrics.lang),
+            _ => continue,
+        };
+        languages.entry(key).or_default().push(lang.clone());
+    }
+    let language = |key: &str, index: usize| {
+        languages
+            .get(key)
+            .and_then(|languages| languages.get(index).or_else(|| languages.last()))
+            .cloned()
+            .unwrap_or_else(|| String::from("eng"))
+    };
+
+    let mut listed_ids = tag
+        .frames()
+        .filter(|frame| id3_field(frame).is_some())
+        .map(|frame| frame.id().to_owned())
+        .collect::<Vec<String>>();
+    listed_ids.sort();
+    listed_ids.dedup();
+    // Frames with the same id that are not shown as text are kept. `add_frame`
+    // replaces frames of the same kind, so repeated frames are added with `extend`.
+    let mut frames = vec![];
+    for id in listed_ids {
+        frames.extend(
+            tag.remove(&id)
+                .into_iter()
+                .filter(|frame| id3_field(frame).is_none()),
+        );
+    }
+
+    for (key, values) in fields.iter().filter(|(_, values)| !values.is_empty()) {
+        let (id, description) = match key.split_once(':') {
+            Some((id, description)) => (id, description.to_owned()),
+            None => (key.as_str(), String::new()),
+        };
+        if !is_valid_id3_key(key) {
+            warn!(
+                "Not writing {} to {}, invalid frame id",
+                key,
+                path.display()
+            );
+            continue;
+        }
+        let content = |(index, value): (usize, &String)| match id {
+            "TXXX" => Content::ExtendedText(ExtendedText {
+                description: description.clone(),
+                value: value.clone(),
+            }),
+            "WXXX" => Content::ExtendedLink(ExtendedLink {
+                description: description.clone(),
+                link: value.clone(),
+            }),
+            "COMM" => Content::Comment(Comment {
+                lang: language(key, index),
+                description: description.clone(),
+                text: value.clone(),
+            }),
+            "USLT" => Content::Lyrics(Lyrics {
+                lang: language(key, index),
+                description: description.clone(),
+                text: value.clone(),
+            }),
+            _ => Content::Link(value.clone()),
+        };
+        if id.starts_with('T') && id != "TXXX" {
+            // ID3v2.4 separates multiple values of a text frame with null bytes
+            frames.push(id3::Frame::with_content(
+                id,
+                Content::Text(values.join("\0")),
+            ));
+        } else {
+            frames.extend(
+                values
+                    .iter()
+                    .enumerate()
+                    .map(|value| id3::Frame::with_content(id, content(value))),
+            );
+        }
+    }
+    tag.extend(frames);
+}
+
+/// Replaces the Vorbis comments `read_fields` lists in a FLAC tag with `fields`, see
+/// `set_id3_fields`
+pub fn set_flac_fields(tag: &mut metaflac::Tag, fields: &Fields, path: &Path) {
+    let comments = tag.vorbis_comments_mut();
+    comments
+        .comments
+        .retain(|key, _| !is_listed_vorbis_key(key));
+    for (key, values) in fields {
+        if !is_valid_vorbis_key(key) {
+            warn!(
+                "Not writing {} to {}, invalid comment name",
+                key,
+                path.display()
+            );
+            continue;
+        }
+        comments.comments.insert(key.clone(), values.clone());
+    }
+}
+
+/// Replaces the atoms `read_fields` lists in an MP4 tag with `fields`, see
+/// `set_id3_fields`. Images and other binary data are kept as they are.
+pub fn set_mp4_fields(tag: &mut mp4ameta::Tag, fields: &Fields, path: &Path) {
+    // Integer atoms like the BPM stay integers of the same size
+    let mut integer_sizes = Vec::new();
+    let mut listed = Vec::new();
+    for (ident, data) in tag.data() {
+        if mp4_key(ident).is_none() || mp4_text(data).is_none() {
+            continue;
+        }
+        if let Data::BeSigned(bytes) = data {
+            integer_sizes.push((ident.clone(), bytes.len()));
+        }
+        if !listed.contains(ident) {
+            listed.push(ident.clone());
+        }
+    }
+    for ident in listed.iter() {
+        tag.remove_data_of(ident);
+    }
+
+    for (key, values) in fields {
+        let ident = match mp4_ident(key) {
+            Some(ident) => ident,
+            None => {
+                warn!(
+                    "Not writing {} to {}, invalid atom name",
+                    key,
+                    path.display()
+                );
+                continue;
+            }
+        };
+        let integer_size = integer_sizes
+            .iter()
+            .find(|(integer_ident, _)| *integer_ident == ident)
+            .map(|(_, size)| *size);
+        for value in values {
+            let data = match (integer_size, value.trim().parse::<i64>()) {
+                (Some(size), Ok(number)) => {
+                    Data::BeSigned(number.to_be_bytes()[8 - size..].to_vec())
+                }
+                _ => Data::Utf8(value.clone()),
+            };
+            tag.add_data(ident.clone(), data);
+        }
+    }
+}
+
+/// Whether `key` can be written as other field to the tag of the file at `path`, by the
+/// backend that reads and writes it
+pub fn is_valid_key(path: &Path, key: &str) -> bool {
+    backend_for(path).is_some_and(|backend| backend.is_valid_key(path, key))
+}
+
+/// Whether `key` can be written to the native tag of the file at `path`
+pub fn is_valid_native_key(path: &Path, key: &str) -> bool {
+    match native_tag(path) {
+        Some(NativeTag::Id3) => is_valid_id3_key(key),
+        Some(NativeTag::Flac) => is_valid_vorbis_key(key),
+        Some(NativeTag::Mp4) => mp4_ident(key).is_some(),
+        None => false,
+    }
+}
+
+/// The key and values of an ID3v2 frame shown as text, `None` for typed and binary frames
+fn id3_field(frame: &id3::Frame) -> Option<(String, Vec<String>)> {
+    if ID3_TYPED_FRAMES.contains(&frame.id()) {
+        return None;
+    }
+    match frame.content() {
+        Content::Text(text) => Some((
+            frame.id().to_owned(),
+            text.split('\0').map(String::from).collect(),
+        )),
+        Content::Link(link) => Some((frame.id().to_owned(), vec![link.clone()])),
+        Content::ExtendedText(text) => Some((
+            format!("TXXX:{}", text.description),
+            vec![text.value.clone()],
+        )),
+        Content::ExtendedLink(link) => Some((
+            format!("WXXX:{}", link.description),
+            vec![link.link.clone()],
+        )),
+        Content::Comment(comment) => Some((
+            format!("COMM:{}", comment.description),
+            vec![comment.text.clone()],
+        )),
+        Content::Lyrics(lyrics) => Some((
+            format!("USLT:{}", lyrics.description),
+            vec![lyrics.text.clone()],
+        )),
+        _ => None,
+    }
+}
+
+/// Text frames, links, user defined frames, comments and lyrics that are not typed
+fn is_valid_id3_key(key: &str) -> bool {
+    let id = key.split_once(':').map_or(key, |(id, _)| id);
+    let has_description = key.contains(':');
+    id.len() == 4
+        && id
+            .bytes()
+            .all(|byte| byte.is_ascii_uppercase() || byte.is_ascii_digit())
+        && (id.starts_with('T') || id.starts_with('W') || id == "COMM" || id == "USLT")
+        && has_description == matches!(id, "TXXX" | "WXXX" | "COMM" | "USLT")
+        && !ID3_TYPED_FRAMES.contains(&id)
+}
+
+fn is_listed_vorbis_key(key: &str) -> bool {
+    !VORBIS_TYPED_KEYS.contains(&key.to_ascii_uppercase().as_str())
+}
+
+/// Vorbis comment names are printable ASCII without "="
+pub fn is_valid_vorbis_key(key: &str) -> bool {
+    !key.is_empty()
+        && key
+            .bytes()
+            .all(|byte| (0x20..=0x7d).contains(&byte) && byte != b'=')
+        && is_listed_vorbis_key(key)
+}
+
+/// The key of an MP4 atom, its four character code or "----:mean:name"
+fn mp4_key(ident: &DataIdent) -> Option<String> {
+    match ident {
+        DataIdent::Fourcc(fourcc) if !MP4_TYPED_ATOMS.contains(&&fourcc.0) => {
+            // Atom names are Latin-1, e.g. "©cmt"
+            Some(fourcc.0.iter().map(|byte| char::from(*byte)).collect())
+        }
+        DataIdent::Fourcc(_) => None,
+        DataIdent::Freeform { mean, name } => {
+            Some(format!("{}{}:{}", MP4_FREEFORM_PREFIX, mean, name))
+        }
+    }
+}
+
+fn mp4_ident(key: &str) -> Option<DataIdent> {
+    if let Some(freeform) = key.strip_prefix(MP4_FREEFORM_PREFIX) {
+        let (mean, name) = freeform.split_once(':')?;
+        if mean.is_empty() || name.is_empty() {
+            return None;
+        }
+        return Some(DataIdent::Freeform {
+            mean: mean.to_owned(),
+            name: name.to_owned(),
+        });
+    }
+    let bytes = key
+        .chars()
+        .map(|c| u8::try_from(u32::from(c)).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    let fourcc = <[u8; 4]>::try_from(bytes).ok()?;
+    if MP4_TYPED_ATOMS.contains(&&fourcc) || &fourcc == b"----" {
+        return None;
+    }
+    Some(DataIdent::Fourcc(Fourcc(fourcc)))
+}
+
+/// The value of an MP4 atom shown as text, `None` for images and other binary data
+fn mp4_text(data: &Data) -> Option<String> {
+    match data {
+        Data::Utf8(text) | Data::Utf16(text) => Some(text.clone()),
+        Data::BeSigned(bytes) if (1..=8).contains(&bytes.len()) => {
+            // Sign extended big endian integer
+            let fill = if bytes[0] & 0x80 != 0 { 0xff } else { 0 };
+            let mut number = [fill; 8];
+            number[8 - bytes.len()..].copy_from_slice(bytes);
+            Some(i64::from_be_bytes(number).to_string())
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs, process};
+
+    #[test]
+    fn keeps_id3_comment_languages() {
+        // MPEG-1 layer III frames with comments in two languages
+        let mut frame = vec![0xff, 0xfb, 0x90, 0x00];
+        frame.resize(417, 0);
+        let path = env::temp_dir().join(format!("easier-tag-{}-fields.mp3", process::id()));
+        fs::write(&path, frame.repeat(4)).unwrap();
+        let mut tag = id3::Tag::new();
+        for (lang, text) in [("eng", "English"), ("deu", "Deutsch")] {
+            tag.add_frame(Comment {
+                lang: String::from(lang),
+                description: String::new(),
+                text: String::from(text),
+            });
+        }
+        tag.add_frame(id3::frame::Popularimeter {
+            user: String::from("user"),
+            rating: 255,
+            counter: 1,
+        });
+        tag.write_to_path(&path, id3::Version::Id3v24).unwrap();
+
+        let fields = Fields::from([
+            (
+                String::from("COMM:"),
+                vec![String::from("Changed"), String::from("Geändert")],
+            ),
+            (String::from("TXXX:A"), vec![String::from("a")]),
+            (String::from("TXXX:B"), vec![String::from("b")]),
+            (
+                String::from("WOAR"),
+                vec![String::from("https://a.example")],
+            ),
+        ]);
+        let mut tag = id3::Tag::read_from_path(&path).unwrap();
+        set_id3_fields(&mut tag, &fields, &path);
+        tag.write_to_path(&path, id3::Version::Id3v24).unwrap();
+        let read = read_fields(&path);
+        let tag = id3::Tag::read_from_path(&path);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(read.unwrap(), fields);
+        let tag = tag.unwrap();
+        let comments = tag
+            .comments()
+            .map(|comment| (comment.lang.as_str(), comment.text.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(comments, vec![("eng", "Changed"), ("deu", "Geändert")]);
+        assert!(tag.get("POPM").is_some());
+    }
+}
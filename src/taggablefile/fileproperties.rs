@@ -0,0 +1,275 @@
+use crate::taggablefile::record::FileProperties;
+use lofty::error::ErrorKind;
+use lofty::mp4::{Mp4Codec, Mp4File};
+use lofty::mpeg::{Layer, MpegFile};
+use lofty::{AudioFile, FileType, ParseOptions, Probe, Tag, TagType, TaggedFile, TaggedFileExt};
+use log::debug;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// Reads the properties of the audio stream of the file at `path` with lofty. Of files
+/// lofty can't read only the size and the extension are known.
+pub fn read_file_properties(path: &Path) -> FileProperties {
+    match read_tagged_file(path) {
+        Ok((tagged_file, codec)) => {
+            let mut properties = file_properties(path, &tagged_file);
+            if let Some(codec) = codec {
+                // MPEG files are known by their layer, e.g. "MP3"
+                if tagged_file.file_type() == FileType::Mpeg {
+                    properties.format = Some(String::from(codec));
+                }
+                properties.codec = Some(String::from(codec));
+            }
+            properties
+        }
+        Err(err) => {
+            debug!("Failed to read the audio properties of {:?}, {}", path, err);
+            read_basic_properties(path)
+        }
+    }
+}
+
+/// The properties known without reading the file, its size and its extension as format
+pub fn read_basic_properties(path: &Path) -> FileProperties {
+    FileProperties {
+        format: path
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_uppercase()),
+        file_size: path.metadata().ok().map(|metadata| metadata.len()),
+        ..FileProperties::default()
+    }
+}
+
+/// The properties of a file read with lofty. The codec of MPEG and MP4 files is only
+/// known to their own file types.
+pub fn file_properties(path: &Path, tagged_file: &TaggedFile) -> FileProperties {
+    let properties = tagged_file.properties();
+    let (format, codec) = match tagged_file.file_type() {
+        FileType::Aac => ("AAC", "AAC"),
+        FileType::Flac => ("FLAC", "FLAC"),
+        FileType::Mpeg => ("MPEG", ""),
+        FileType::Mp4 => ("MP4", ""),
+        FileType::Vorbis => ("Ogg", "Vorbis"),
+        FileType::Opus => ("Ogg", "Opus"),
+        FileType::Speex => ("Ogg", "Speex"),
+        FileType::Wav => ("WAV", "PCM"),
+        FileType::Aiff => ("AIFF", "PCM"),
+        FileType::Ape => ("APE", "Monkey's Audio"),
+        FileType::WavPack => ("WavPack", "WavPack"),
+        FileType::Mpc => ("Musepack", "Musepack"),
+        _ => ("", ""),
+    };
+    let tag_formats = tagged_file
+        .tags()
+        .iter()
+        .map(|tag| match tag.tag_type() {
+            TagType::Ape => "APEv2",
+            TagType::Id3v1 => "ID3v1",
+            TagType::Id3v2 => "ID3v2",
+            TagType::Mp4Ilst => "MP4",
+            TagType::VorbisComments => "Vorbis Comments",
+            TagType::RiffInfo => "RIFF INFO",
+            TagType::AiffText => "AIFF Text",
+            _ => "",
+        })
+        .filter(|name| !name.is_empty())
+        .collect::<Vec<&str>>();
+
+    let basic = read_basic_properties(path);
+    FileProperties {
+        duration: Some(properties.duration()).filter(|duration| !duration.is_zero()),
+        format: Some(String::from(format))
+            .filter(|format| !format.is_empty())
+            .or(basic.format),
+        codec: Some(String::from(codec)).filter(|codec| !codec.is_empty()),
+        bitrate: properties.audio_bitrate().or(properties.overall_bitrate()),
+        sample_rate: properties.sample_rate(),
+        bit_depth: properties.bit_depth(),
+        channels: properties.channels(),
+        tag_format: Some(tag_formats.join(", ")).filter(|text| !text.is_empty()),
+        file_size: basic.file_size,
+    }
+}
+
+/// Reads the file at `path` by its content, MPEG and MP4 files along with their codec
+fn read_tagged_file(path: &Path) -> lofty::Result<(TaggedFile, Option<&'static str>)> {
+    let mut file = File::open(path)?;
+    let file_type = Probe::new(&mut file).guess_file_type()?.file_type();
+    Ok(match file_type {
+        Some(FileType::Mpeg) => {
+            let (mpeg_file, tag_skipped) = match MpegFile::read_from(&mut file, ParseOptions::new())
+            {
+                Ok(mpeg_file) => (mpeg_file, false),
+                // lofty fails on some ID3v2 tags the other libraries read, e.g. ID3v2.4
+                // text frames with several values. The audio behind them can still be read.
+                Err(err) if matches!(err.kind(), ErrorKind::Id3v2(_)) => {
+                    let mut audio = WithoutId3v2::open(file)?;
+                    (MpegFile::read_from(&mut audio, ParseOptions::new())?, true)
+                }
+                Err(err) => return Err(err),
+            };
+            let codec = match mpeg_file.properties().layer() {
+                Layer::Layer1 => "MP1",
+                Layer::Layer2 => "MP2",
+                Layer::Layer3 => "MP3",
+            };
+            let mut tagged_file = TaggedFile::from(mpeg_file);
+            if tag_skipped {
+                tagged_file.insert_tag(Tag::new(TagType::Id3v2));
+            }
+            (tagged_file, Some(codec))
+        }
+        Some(FileType::Mp4) => {
+            let mp4_file = Mp4File::read_from(&mut file, ParseOptions::new())?;
+            let codec = match mp4_file.properties().codec() {
+                Mp4Codec::AAC => Some("AAC"),
+                Mp4Codec::ALAC => Some("ALAC"),
+                Mp4Codec::MP3 => Some("MP3"),
+                Mp4Codec::FLAC => Some("FLAC"),
+                _ => None,
+            };
+            (mp4_file.into(), codec)
+        }
+        _ => (Probe::new(&mut file).guess_file_type()?.read()?, None),
+    })
+}
+
+/// A file seen without the ID3v2 tag at its start
+struct WithoutId3v2 {
+    file: File,
+    // Where the audio starts in the file
+    start: u64,
+}
+
+impl WithoutId3v2 {
+    fn open(mut file: File) -> io::Result<Self> {
+        let mut header = [0; 10];
+        file.seek(SeekFrom::Start(0))?;
+        file.read_exact(&mut header)?;
+        let start = match header {
+            [b'I', b'D', b'3', _, _, flags, size @ ..] => {
+                // Syncsafe integer, 7 bits per byte
+                let size = size
+                    .iter()
+                    .fold(0, |size, byte| (size << 7) | u64::from(byte & 0x7f));
+                let footer = if flags & 0x10 != 0 { 10 } else { 0 };
+                10 + size + footer
+            }
+            _ => 0,
+        };
+        file.seek(SeekFrom::Start(start))?;
+        Ok(Self { file, start })
+    }
+}
+
+impl Read for WithoutId3v2 {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        self.file.read(buffer)
+    }
+}
+
+impl Seek for WithoutId3v2 {
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        let position = match position {
+            SeekFrom::Start(offset) => SeekFrom::Start(self.start + offset),
+            position => position,
+        };
+        self.file
+            .seek(position)?
+            .checked_sub(self.start)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "seek into the ID3v2 tag"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use id3::TagLike;
+    use std::time::Duration;
+    use std::{env, fs, process};
+
+    /// Writes `data` to a file in the temporary folder and reads its properties
+    fn read(name: &str, data: &[u8]) -> FileProperties {
+        let path = env::temp_dir().join(format!("easier-tag-{}-{}", process::id(), name));
+        fs::write(&path, data).unwrap();
+        let properties = read_file_properties(&path);
+        fs::remove_file(&path).unwrap();
+        properties
+    }
+
+    #[test]
+    fn reads_wav_properties() {
+        // One second of 16 bit stereo PCM at 44.1 kHz
+        let samples = 44100 * 4;
+        let mut data = b"RIFF".to_vec();
+        data.extend(&(36 + samples as u32).to_le_bytes());
+        data.extend(b"WAVEfmt ");
+        data.extend(&16u32.to_le_bytes());
+        data.extend(&1u16.to_le_bytes());
+        data.extend(&2u16.to_le_bytes());
+        data.extend(&44100u32.to_le_bytes());
+        data.extend(&(44100u32 * 4).to_le_bytes());
+        data.extend(&4u16.to_le_bytes());
+        data.extend(&16u16.to_le_bytes());
+        data.extend(b"data");
+        data.extend(&(samples as u32).to_le_bytes());
+        data.resize(data.len() + samples, 0);
+
+        let properties = read("properties.wav", &data);
+        assert_eq!(properties.format.as_deref(), Some("WAV"));
+        assert_eq!(properties.codec.as_deref(), Some("PCM"));
+        assert_eq!(properties.duration, Some(Duration::from_secs(1)));
+        assert_eq!(properties.sample_rate, Some(44100));
+        assert_eq!(properties.bit_depth, Some(16));
+        assert_eq!(properties.channels, Some(2));
+        assert_eq!(properties.file_size, Some(data.len() as u64));
+        assert_eq!(properties.tag_format, None);
+    }
+
+    #[test]
+    fn reads_mpeg_layer() {
+        // MPEG-1 layer III frames at 128 kbit/s and 44.1 kHz, 417 bytes each
+        let mut frame = vec![0xff, 0xfb, 0x90, 0x00];
+        frame.resize(417, 0);
+        let data = frame.repeat(40);
+
+        let properties = read("properties.mp3", &data);
+        assert_eq!(properties.format.as_deref(), Some("MP3"));
+        assert_eq!(properties.codec.as_deref(), Some("MP3"));
+        assert_eq!(properties.sample_rate, Some(44100));
+        assert_eq!(properties.channels, Some(2));
+        assert_eq!(properties.bitrate, Some(128));
+    }
+
+    #[test]
+    fn reads_mpeg_behind_unreadable_id3v2_tag() {
+        let mut frame = vec![0xff, 0xfb, 0x90, 0x00];
+        frame.resize(417, 0);
+        let path = env::temp_dir().join(format!("easier-tag-{}-id3v24.mp3", process::id()));
+        fs::write(&path, frame.repeat(40)).unwrap();
+        let mut tag = id3::Tag::new();
+        tag.set_text_values("TPE1", ["A", "B"]);
+        tag.set_title("Title");
+        tag.write_to_path(&path, id3::Version::Id3v24).unwrap();
+
+        let properties = read_file_properties(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(properties.codec.as_deref(), Some("MP3"));
+        assert_eq!(properties.sample_rate, Some(44100));
+        assert_eq!(properties.tag_format.as_deref(), Some("ID3v2"));
+    }
+
+    #[test]
+    fn falls_back_to_basic_properties() {
+        let properties = read("properties.ogg", b"not an audio file");
+        assert_eq!(
+            properties,
+            FileProperties {
+                format: Some(String::from("OGG")),
+                file_size: Some(17),
+                ..FileProperties::default()
+            }
+        );
+    }
+}
@@ -0,0 +1,229 @@
+use crate::taggablefile::TaggableFile;
+use gtk::{
+    glib,
+    glib::{ParamSpec, ParamSpecBoolean, Value},
+    prelude::*,
+    subclass::prelude::*,
+};
+use log::warn;
+use once_cell::sync::Lazy;
+use std::cell::{Cell, RefCell};
+use std::path::PathBuf;
+
+/// A single change of a TaggableFile
+#[derive(Clone, Debug)]
+pub enum Change {
+    Property {
+        taggable_file: TaggableFile,
+        property: String,
+        old: Value,
+        new: Value,
+    },
+    // The file was moved on disk from `old` to `new`
+    Rename {
+        taggable_file: TaggableFile,
+        old: PathBuf,
+        new: PathBuf,
+    },
+}
+
+impl Change {
+    fn revert(&self) {
+        match self {
+            Change::Property {
+                taggable_file,
+                property,
+                old,
+                ..
+            } => taggable_file.set_property_from_value(property, old),
+            Change::Rename {
+                taggable_file, old, ..
+            } => {
+                if let Err(err) = taggable_file.rename_to(old) {
+                    warn!("Failed to move {} back, {}", old.display(), err);
+                }
+            }
+        }
+    }
+
+    fn taggable_file_mut(&mut self) -> &mut TaggableFile {
+        match self {
+            Change::Property { taggable_file, .. } | Change::Rename { taggable_file, .. } => {
+                taggable_file
+            }
+        }
+    }
+
+    fn apply(&self) {
+        match self {
+            Change::Property {
+                taggable_file,
+                property,
+                new,
+                ..
+            } => taggable_file.set_property_from_value(property, new),
+            Change::Rename {
+                taggable_file, new, ..
+            } => {
+                if let Err(err) = taggable_file.rename_to(new) {
+                    warn!("Failed to move {} again, {}", new.display(), err);
+                }
+            }
+        }
+    }
+}
+
+mod imp {
+    use super::*;
+
+    #[derive(Debug, Default)]
+    pub struct TagHistory {
+        // Every entry is one undo step, which may consist of several changes
+        pub undo_stack: RefCell<Vec<Vec<Change>>>,
+        pub redo_stack: RefCell<Vec<Vec<Change>>>,
+        pub group: RefCell<Vec<Change>>,
+        pub group_depth: Cell<u32>,
+        // Set while changes are undone or redone so that they are not recorded again
+        pub replaying: Cell<bool>,
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for TagHistory {
+        const NAME: &'static str = "TagHistory";
+        type Type = super::TagHistory;
+    }
+
+    impl ObjectImpl for TagHistory {
+        fn properties() -> &'static [ParamSpec] {
+            static PROPERTIES: Lazy<Vec<ParamSpec>> = Lazy::new(|| {
+                vec![
+                    ParamSpecBoolean::builder("can-undo").read_only().build(),
+                    ParamSpecBoolean::builder("can-redo").read_only().build(),
+                ]
+            });
+            PROPERTIES.as_ref()
+        }
+
+        fn property(&self, _id: usize, pspec: &ParamSpec) -> Value {
+            match pspec.name() {
+                "can-undo" => (!self.undo_stack.borrow().is_empty()).to_value(),
+                "can-redo" => (!self.redo_stack.borrow().is_empty()).to_value(),
+                _ => unimplemented!(),
+            }
+        }
+    }
+}
+
+glib::wrapper! {
+    pub struct TagHistory(ObjectSubclass<imp::TagHistory>);
+}
+
+impl TagHistory {
+    pub fn new() -> Self {
+        glib::Object::new(&[])
+    }
+
+    /// Adds a change as its own undo step, or to the current group
+    pub fn record(&self, change: Change) {
+        let imp = self.imp();
+        if imp.replaying.get() {
+            return;
+        }
+
+        if imp.group_depth.get() > 0 {
+            imp.group.borrow_mut().push(change);
+        } else {
+            self.push_step(vec![change]);
+        }
+    }
+
+    /// Runs `f` and makes all changes it records a single undo step
+    pub fn group<F: FnOnce()>(&self, f: F) {
+        let imp = self.imp();
+        imp.group_depth.set(imp.group_depth.get() + 1);
+        f();
+        imp.group_depth.set(imp.group_depth.get() - 1);
+
+        if imp.group_depth.get() == 0 {
+            let changes = imp.group.take();
+            if !changes.is_empty() {
+                self.push_step(changes);
+            }
+        }
+    }
+
+    pub fn undo(&self) {
+        let imp = self.imp();
+        let step = imp.undo_stack.borrow_mut().pop();
+        if let Some(step) = step {
+            imp.replaying.set(true);
+            for change in step.iter().rev() {
+                change.revert();
+            }
+            imp.replaying.set(false);
+            imp.redo_stack.borrow_mut().push(step);
+            self.notify_state();
+        }
+    }
+
+    pub fn redo(&self) {
+        let imp = self.imp();
+        let step = imp.redo_stack.borrow_mut().pop();
+        if let Some(step) = step {
+            imp.replaying.set(true);
+            for change in step.iter() {
+                change.apply();
+            }
+            imp.replaying.set(false);
+            imp.undo_stack.borrow_mut().push(step);
+            self.notify_state();
+        }
+    }
+
+    /// Forgets all steps, e.g. when the files they refer to are no longer shown
+    pub fn clear(&self) {
+        let imp = self.imp();
+        imp.undo_stack.borrow_mut().clear();
+        imp.redo_stack.borrow_mut().clear();
+        imp.group.borrow_mut().clear();
+        self.notify_state();
+    }
+
+    /// Lets the changes of `old` apply to `new`, which replaces it after its file was read
+    /// again
+    pub fn replace_file(&self, old: &TaggableFile, new: &TaggableFile) {
+        let imp = self.imp();
+        let mut undo_stack = imp.undo_stack.borrow_mut();
+        let mut redo_stack = imp.redo_stack.borrow_mut();
+        let mut group = imp.group.borrow_mut();
+        let changes = undo_stack
+            .iter_mut()
+            .chain(redo_stack.iter_mut())
+            .flatten()
+            .chain(group.iter_mut());
+        for change in changes {
+            let taggable_file = change.taggable_file_mut();
+            if taggable_file == old {
+                *taggable_file = new.clone();
+            }
+        }
+    }
+
+    fn push_step(&self, step: Vec<Change>) {
+        let imp = self.imp();
+        imp.undo_stack.borrow_mut().push(step);
+        imp.redo_stack.borrow_mut().clear();
+        self.notify_state();
+    }
+
+    fn notify_state(&self) {
+        self.notify("can-undo");
+        self.notify("can-redo");
+    }
+}
+
+impl Default for TagHistory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
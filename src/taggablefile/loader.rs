@@ -0,0 +1,131 @@
+use crate::taggablefile::backend::backend_for;
+use crate::taggablefile::error::{Result, TagError};
+use crate::taggablefile::fileproperties::read_basic_properties;
+use crate::taggablefile::record::{FileProperties, TagRecord};
+use crate::taggablefile::TaggableFile;
+use gtk::{gio, glib};
+use log::debug;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// Most worker threads reading tags at the same time
+const MAX_WORKERS: usize = 8;
+
+/// Reads the tag of the file at `path` with the backend for its format
+pub fn read_record(path: &Path) -> Result<TagRecord> {
+    backend_for(path).ok_or(TagError::Unsupported)?.read(path)
+}
+
+/// Writes the fields of `record` that differ from `saved`, the tag as it is on disk, to
+/// the file at `path` with the backend for its format
+pub fn write_record(path: &Path, record: &TagRecord, saved: &TagRecord) -> Result<()> {
+    backend_for(path)
+        .ok_or(TagError::Unsupported)?
+        .write(path, record, saved)
+}
+
+/// Whether the file at `path` is an audio file judging by its name
+fn is_audio_file(path: &Path) -> bool {
+    let (content_type, _) = gio::content_type_guess(Some(path), &[]);
+    gio::content_type_get_mime_type(&content_type)
+        .is_some_and(|mime_type| mime_type.starts_with("audio/"))
+}
+
+/// What was read from a file on a worker thread. Audio files whose tag can't be read
+/// are still listed with what is known about the file itself.
+enum Loaded {
+    Tag(TagRecord),
+    // An audio file in a format no backend supports
+    Unsupported(FileProperties),
+    // An audio file whose tag could not be read, with the reason
+    Failed(FileProperties, String),
+    // Not an audio file
+    Skipped,
+}
+
+fn read_file(path: &Path) -> Loaded {
+    match read_record(path) {
+        Ok(record) => Loaded::Tag(record),
+        Err(TagError::Unsupported) if is_audio_file(path) => {
+            Loaded::Unsupported(read_basic_properties(path))
+        }
+        Err(TagError::Unsupported) => Loaded::Skipped,
+        Err(err) => {
+            debug!("Failed to read the tag of {}, {}", path.display(), err);
+            Loaded::Failed(read_basic_properties(path), err.to_string())
+        }
+    }
+}
+
+fn into_taggable_file(path: &Path, loaded: Loaded) -> Option<TaggableFile> {
+    match loaded {
+        Loaded::Tag(record) => Some(TaggableFile::new(path, record)),
+        Loaded::Unsupported(file) => Some(TaggableFile::new_unsupported(path, file)),
+        Loaded::Failed(file, error) => Some(TaggableFile::new_failed(path, file, &error)),
+        Loaded::Skipped => None,
+    }
+}
+
+/// Reads the tags of a set of files on worker threads
+#[derive(Debug)]
+pub struct TagLoader {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl TagLoader {
+    /// Starts reading the tags of `paths`. `on_file` is called on the main thread once
+    /// for every path, with `None` for files that are not audio files. Audio files whose
+    /// tag can't be read are passed as unsupported or failed files.
+    pub fn start<F: Fn(PathBuf, Option<TaggableFile>) + 'static>(
+        paths: Vec<PathBuf>,
+        on_file: F,
+    ) -> Self {
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let (sender, receiver) = glib::MainContext::channel(glib::PRIORITY_DEFAULT_IDLE);
+
+        let workers = thread::available_parallelism()
+            .map(|workers| workers.get())
+            .unwrap_or(2)
+            .min(MAX_WORKERS)
+            .min(paths.len());
+        let queue = Arc::new(Mutex::new(paths));
+        for _ in 0..workers {
+            let queue = queue.clone();
+            let cancelled = cancelled.clone();
+            let sender = sender.clone();
+            thread::spawn(move || loop {
+                if cancelled.load(Ordering::Relaxed) {
+                    break;
+                }
+                let path = match queue.lock().unwrap().pop() {
+                    Some(path) => path,
+                    None => break,
+                };
+                let loaded = read_file(&path);
+                if sender.send((path, loaded)).is_err() {
+                    break;
+                }
+            });
+        }
+
+        // The receiver is removed once all workers dropped their sender
+        let receiver_cancelled = cancelled.clone();
+        receiver.attach(None, move |(path, loaded): (PathBuf, Loaded)| {
+            if receiver_cancelled.load(Ordering::Relaxed) {
+                return glib::Continue(false);
+            }
+            let taggable_file = into_taggable_file(&path, loaded);
+            on_file(path, taggable_file);
+            glib::Continue(true)
+        });
+
+        Self { cancelled }
+    }
+
+    /// Stops reading tags, `on_file` is not called anymore after this
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+}
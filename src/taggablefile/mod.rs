@@ -1,37 +1,87 @@
+use gettextrs::gettext;
 use gtk::{
     gdk::Texture,
     glib,
-    glib::{Bytes, Object, ParamSpec, ParamSpecBoxed, ParamSpecObject, ParamSpecString, Value},
+    glib::{
+        Bytes, Object, ParamSpec, ParamSpecBoolean, ParamSpecBoxed, ParamSpecObject,
+        ParamSpecString, Value, WeakRef,
+    },
     prelude::*,
     subclass::prelude::*,
 };
 use log::warn;
 use once_cell::sync::Lazy;
-use std::cell::{Cell, RefCell};
+use std::cell::{Cell, Ref, RefCell};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
 
+pub mod backend;
+pub mod error;
+pub mod fields;
+pub mod fileproperties;
+pub mod history;
+pub mod loader;
+pub mod native;
+pub mod pattern;
+pub mod pictures;
+pub mod query;
+pub mod record;
+pub mod sorting;
 pub mod taggablefilelist;
 
+use fields::Fields;
+use history::{Change, TagHistory};
+pub use record::{is_valid_value, split_list};
+use record::{FileProperties, Picture, PictureType, TagRecord};
+
+/// Properties that are stored in the tag of the file and can be edited
+pub const TAG_PROPERTIES: &[&str] = &[
+    "title",
+    "album",
+    "composer",
+    "genre",
+    "year",
+    "disc",
+    "total-discs",
+    "track",
+    "total-tracks",
+    "artists",
+    "album-artists",
+    "pictures",
+    "extra",
+];
+
+/// The pictures of a tag as GObject property value
+#[derive(Clone, Debug, Default, PartialEq, glib::Boxed)]
+#[boxed_type(name = "EasierTagPictures")]
+pub struct Pictures(pub Vec<Picture>);
+
+/// The fields of a tag without typed counterpart as GObject property value
+#[derive(Clone, Debug, Default, PartialEq, glib::Boxed)]
+#[boxed_type(name = "EasierTagExtraFields")]
+pub struct ExtraFields(pub Fields);
+
 mod imp {
     use super::*;
 
     #[derive(Default)]
     pub struct TaggableFile {
-        pub filename: RefCell<Option<String>>,
-        pub title: RefCell<Option<String>>,
-        pub album: RefCell<Option<String>>,
-        pub composer: RefCell<Option<String>>,
-        pub genre: RefCell<Option<String>>,
-        pub duration: RefCell<Option<String>>,
-        pub year: RefCell<Option<String>>,
-        pub disc: RefCell<Option<String>>,
-        pub total_discs: RefCell<Option<String>>,
-        pub track: RefCell<Option<String>>,
-        pub total_tracks: RefCell<Option<String>>,
-
-        pub artists: RefCell<Vec<String>>,
-        pub album_artists: RefCell<Vec<String>>,
-
+        // Kept as is, filenames don't need to be valid UTF-8
+        pub path: RefCell<PathBuf>,
+        pub record: RefCell<TagRecord>,
+        // Decoded front cover of `record`
         pub cover: RefCell<Option<Texture>>,
+
+        // The tag as it is on disk
+        pub saved: RefCell<TagRecord>,
+        pub modified: Cell<bool>,
+        // No backend can read or write the tag of the file
+        pub unsupported: Cell<bool>,
+        // Why the tag of the file could not be read
+        pub error: RefCell<Option<String>>,
+
+        pub history: WeakRef<TagHistory>,
     }
 
     #[glib::object_subclass]
@@ -44,12 +94,23 @@ mod imp {
         fn properties() -> &'static [ParamSpec] {
             static PROPERTIES: Lazy<Vec<ParamSpec>> = Lazy::new(|| {
                 vec![
-                    ParamSpecString::builder("filename").build(),
+                    // Shown with invalid UTF-8 replaced, use `TaggableFile::path` for the
+                    // path itself
+                    ParamSpecString::builder("path").read_only().build(),
+                    ParamSpecString::builder("filename").read_only().build(),
                     ParamSpecString::builder("title").build(),
                     ParamSpecString::builder("album").build(),
                     ParamSpecString::builder("composer").build(),
                     ParamSpecString::builder("genre").build(),
-                    ParamSpecString::builder("duration").build(),
+                    ParamSpecString::builder("duration").read_only().build(),
+                    ParamSpecString::builder("file-size").read_only().build(),
+                    ParamSpecString::builder("format").read_only().build(),
+                    ParamSpecString::builder("codec").read_only().build(),
+                    ParamSpecString::builder("bitrate").read_only().build(),
+                    ParamSpecString::builder("sample-rate").read_only().build(),
+                    ParamSpecString::builder("bit-depth").read_only().build(),
+                    ParamSpecString::builder("channels").read_only().build(),
+                    ParamSpecString::builder("tag-format").read_only().build(),
                     ParamSpecString::builder("year").build(),
                     ParamSpecString::builder("disc").build(),
                     ParamSpecString::builder("total-discs").build(),
@@ -57,63 +118,92 @@ mod imp {
                     ParamSpecString::builder("total-tracks").build(),
                     ParamSpecBoxed::builder::<Vec<String>>("artists").build(),
                     ParamSpecBoxed::builder::<Vec<String>>("album-artists").build(),
-                    ParamSpecObject::builder::<Texture>("cover").build(),
+                    ParamSpecBoxed::builder::<Pictures>("pictures").build(),
+                    ParamSpecBoxed::builder::<ExtraFields>("extra").build(),
+                    ParamSpecObject::builder::<Texture>("cover")
+                        .read_only()
+                        .build(),
+                    ParamSpecBoolean::builder("modified").read_only().build(),
+                    ParamSpecBoolean::builder("unsupported").read_only().build(),
+                    ParamSpecString::builder("error").read_only().build(),
                 ]
             });
             PROPERTIES.as_ref()
         }
 
         fn set_property(&self, _id: usize, value: &Value, pspec: &ParamSpec) {
-            if let Ok(value) = value.get::<String>() {
-                match pspec.name() {
-                    "filename" => self.filename.replace(Some(value)),
-                    "title" => self.title.replace(Some(value)),
-                    "album" => self.album.replace(Some(value)),
-                    "composer" => self.composer.replace(Some(value)),
-                    "genre" => self.genre.replace(Some(value)),
-                    "duration" => self.duration.replace(Some(value)),
-                    "year" => self.year.replace(Some(value)),
-                    "disc" => self.disc.replace(Some(value)),
-                    "total-discs" => self.total_discs.replace(Some(value)),
-                    "track" => self.track.replace(Some(value)),
-                    "total-tracks" => self.total_tracks.replace(Some(value)),
-                    _ => unimplemented!(),
-                };
-            }
-            if let Ok(value) = value.get::<Vec<String>>() {
-                match pspec.name() {
-                    "artists" => self.artists.replace(value),
-                    "album-artists" => self.album_artists.replace(value),
-                    _ => unimplemented!(),
-                };
+            let old_value = self.property(_id, pspec);
+            let new_value = value.clone();
+            let mut before = TagRecord::default();
+            before.copy_field(&self.record.borrow(), pspec.name());
+
+            match pspec.name() {
+                "artists" | "album-artists" => {
+                    let list = value.get::<Vec<String>>().unwrap();
+                    let mut record = self.record.borrow_mut();
+                    match pspec.name() {
+                        "artists" => record.artists = list,
+                        _ => record.album_artists = list,
+                    }
+                }
+                "pictures" => {
+                    let pictures = value.get::<Pictures>().unwrap();
+                    self.record.borrow_mut().pictures = pictures.0;
+                    self.update_cover();
+                }
+                "extra" => {
+                    let extra = value.get::<ExtraFields>().unwrap();
+                    self.record.borrow_mut().extra = extra.0;
+                }
+                name => {
+                    let text = value.get::<Option<String>>().unwrap().unwrap_or_default();
+                    if let Err(err) = self.record.borrow_mut().set_text(name, &text) {
+                        warn!("Not setting {}, {}", name, err);
+                        return;
+                    }
+                }
             }
-            if let Ok(value) = value.get::<Texture>() {
-                match pspec.name() {
-                    "cover" => self.cover.replace(Some(value)),
-                    _ => unimplemented!(),
-                };
+            self.update_modified();
+
+            // Setting a field to the value it already has is no edit to undo
+            let unchanged = self.record.borrow().same_field(&before, pspec.name());
+            if !unchanged && TAG_PROPERTIES.iter().any(|name| *name == pspec.name()) {
+                if let Some(history) = self.history.upgrade() {
+                    history.record(Change::Property {
+                        taggable_file: self.obj().clone(),
+                        property: pspec.name().to_owned(),
+                        old: old_value,
+                        new: new_value,
+                    });
+                }
             }
         }
 
         fn property(&self, _id: usize, pspec: &ParamSpec) -> Value {
             match pspec.name() {
-                "filename" => self.filename.borrow().to_value(),
-                "title" => self.title.borrow().to_value(),
-                "album" => self.album.borrow().to_value(),
-                "composer" => self.composer.borrow().to_value(),
-                "genre" => self.genre.borrow().to_value(),
-                "duration" => self.duration.borrow().to_value(),
-                "year" => self.year.borrow().to_value(),
-                "disc" => self.disc.borrow().to_value(),
-                "total-discs" => self.total_discs.borrow().to_value(),
-                "track" => self.track.borrow().to_value(),
-                "total-tracks" => self.total_tracks.borrow().to_value(),
-
-                "artists" => self.artists.borrow().to_value(),
-                "album-artists" => self.album_artists.borrow().to_value(),
-
+                "path" => self.path.borrow().to_string_lossy().into_owned().to_value(),
+                "filename" => self
+                    .path
+                    .borrow()
+                    .file_name()
+                    .map(|filename| filename.to_string_lossy().into_owned())
+                    .unwrap_or_default()
+                    .to_value(),
+                "artists" => self.record.borrow().artists.to_value(),
+                "album-artists" => self.record.borrow().album_artists.to_value(),
+                "pictures" => Pictures(self.record.borrow().pictures.clone()).to_value(),
+                "extra" => ExtraFields(self.record.borrow().extra.clone()).to_value(),
                 "cover" => self.cover.borrow().to_value(),
-                _ => unimplemented!(),
+                "modified" => self.modified.get().to_value(),
+                "unsupported" => self.unsupported.get().to_value(),
+                "error" => self.error.borrow().to_value(),
+                // Missing fields are shown as empty text
+                name => self
+                    .record
+                    .borrow()
+                    .text(name)
+                    .unwrap_or_default()
+                    .to_value(),
             }
         }
 
@@ -121,6 +211,24 @@ mod imp {
             self.parent_constructed();
         }
     }
+
+    impl TaggableFile {
+        /// Decodes the front cover again after the pictures changed
+        pub fn update_cover(&self) {
+            let cover = self.record.borrow().front_cover().and_then(decode_picture);
+            if *self.cover.borrow() != cover {
+                self.cover.replace(cover);
+                self.obj().notify("cover");
+            }
+        }
+
+        pub fn update_modified(&self) {
+            let modified = !self.obj().modified_properties().is_empty();
+            if modified != self.modified.replace(modified) {
+                self.obj().notify("modified");
+            }
+        }
+    }
 }
 
 glib::wrapper! {
@@ -128,63 +236,333 @@ glib::wrapper! {
 }
 
 impl TaggableFile {
-    pub fn new(
-        filename: &str,
-        title: &str,
-        album: &str,
-        composer: &str,
-        genre: &str,
-        duration: Option<f64>,
-        year: Option<i32>,
-        disc: Option<u16>,
-        total_discs: Option<u16>,
-        track: Option<u16>,
-        total_tracks: Option<u16>,
-
-        artists: Vec<String>,
-        album_artists: Vec<String>,
-
-        cover: Option<audiotags::Picture>,
-    ) -> Self {
-        let mut filetag_obj = Object::builder()
-            .property("filename", filename)
-            .property("title", title)
-            .property("album", album)
-            .property("composer", composer)
-            .property("genre", genre)
-            .property("artists", artists)
-            .property("album-artists", album_artists);
-
-        if let Some(value) = duration {
-            filetag_obj = filetag_obj.property("duration", value.to_string());
-        }
-        if let Some(value) = year {
-            filetag_obj = filetag_obj.property("year", value.to_string());
-        }
-        if let Some(value) = disc {
-            filetag_obj = filetag_obj.property("disc", value.to_string());
-        }
-        if let Some(value) = total_discs {
-            filetag_obj = filetag_obj.property("total-discs", value.to_string());
-        }
-        if let Some(value) = track {
-            filetag_obj = filetag_obj.property("track", value.to_string());
-        }
-        if let Some(value) = total_tracks {
-            filetag_obj = filetag_obj.property("total-tracks", value.to_string());
-        }
-
-        if let Some(cover) = cover {
-            if let Ok(texture) = Texture::from_bytes(&Bytes::from_owned(cover.data.to_vec())) {
-                filetag_obj = filetag_obj.property("cover", texture);
-            } else {
-                warn!(
-                    "{} has cover metadata but it is in an unsupported format.",
-                    filename
-                );
+    pub fn new(path: &Path, record: TagRecord) -> Self {
+        let taggable_file: Self = Object::builder().build();
+
+        let imp = taggable_file.imp();
+        imp.path.replace(path.to_owned());
+        imp.cover
+            .replace(record.front_cover().and_then(decode_picture));
+        imp.saved.replace(record.clone());
+        imp.record.replace(record);
+        taggable_file
+    }
+
+    /// Creates a file whose format no backend supports. It is listed with its audio
+    /// properties, but its tag can't be edited.
+    pub fn new_unsupported(path: &Path, file: FileProperties) -> Self {
+        let taggable_file = Self::new(
+            path,
+            TagRecord {
+                file,
+                ..TagRecord::default()
+            },
+        );
+        taggable_file.imp().unsupported.set(true);
+        taggable_file
+    }
+
+    /// Creates a file whose tag could not be read because of `error`. It is listed with
+    /// what is known about the file, but its tag can't be edited.
+    pub fn new_failed(path: &Path, file: FileProperties, error: &str) -> Self {
+        let taggable_file = Self::new(
+            path,
+            TagRecord {
+                file,
+                ..TagRecord::default()
+            },
+        );
+        taggable_file.imp().error.replace(Some(error.to_owned()));
+        taggable_file
+    }
+
+    /// Whether the tag of the file can be edited, which is not the case for files in
+    /// unsupported formats and files whose tag could not be read
+    pub fn is_editable(&self) -> bool {
+        let imp = self.imp();
+        !imp.unsupported.get() && imp.error.borrow().is_none()
+    }
+
+    /// The path of the file on disk
+    pub fn path(&self) -> PathBuf {
+        self.imp().path.borrow().clone()
+    }
+
+    /// The typed tag values currently shown
+    pub fn record(&self) -> TagRecord {
+        self.imp().record.borrow().clone()
+    }
+
+    /// Borrows the tag values without copying them, e.g. to compare files while sorting
+    pub fn borrow_record(&self) -> Ref<'_, TagRecord> {
+        self.imp().record.borrow()
+    }
+
+    /// The decoded front cover
+    pub fn cover(&self) -> Option<Texture> {
+        self.imp().cover.borrow().clone()
+    }
+
+    pub fn pictures(&self) -> Vec<Picture> {
+        self.imp().record.borrow().pictures.clone()
+    }
+
+    /// Replaces all pictures. Files whose tag can't be edited are left as they are.
+    pub fn set_pictures(&self, pictures: Vec<Picture>) {
+        if !self.is_editable() {
+            return;
+        }
+        self.set_property("pictures", Pictures(pictures));
+    }
+
+    /// The fields without typed counterpart by their native key
+    pub fn extra(&self) -> Fields {
+        self.imp().record.borrow().extra.clone()
+    }
+
+    pub fn set_extra(&self, extra: Fields) {
+        self.set_property("extra", ExtraFields(extra));
+    }
+
+    /// Replaces the front cover keeping the other pictures, `None` removes it. Files whose
+    /// tag can't be edited are left as they are.
+    pub fn set_front_cover(&self, cover: Option<Picture>) {
+        if !self.is_editable() {
+            return;
+        }
+        let mut pictures = TagRecord {
+            pictures: self.imp().record.borrow().pictures.clone(),
+            ..TagRecord::default()
+        };
+        pictures.set_front_cover(cover);
+        self.set_pictures(pictures.pictures);
+    }
+
+    /// Records all further changes of the tag properties in `history`
+    pub fn set_history(&self, history: &TagHistory) {
+        self.imp().history.set(Some(history));
+    }
+
+    pub fn history(&self) -> Option<TagHistory> {
+        self.imp().history.upgrade()
+    }
+
+    /// Returns a text property, or a list property joined the way it is shown in the UI
+    pub fn property_text(&self, property: &str) -> String {
+        match property {
+            "path" | "filename" => self.property::<String>(property),
+            _ => self
+                .imp()
+                .record
+                .borrow()
+                .text(property)
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Sets a text property, or a list property from its comma separated representation.
+    /// Files whose tag can't be edited are left as they are.
+    pub fn set_property_text(&self, property: &str, text: &str) {
+        if !self.is_editable() {
+            return;
+        }
+        match property {
+            "artists" | "album-artists" => self.set_property(property, split_list(text)),
+            _ => self.set_property(property, text.trim()),
+        }
+    }
+
+    /// Returns the names of the tag properties that differ from what is on disk
+    pub fn modified_properties(&self) -> Vec<&'static str> {
+        let imp = self.imp();
+        let record = imp.record.borrow();
+        let saved = imp.saved.borrow();
+        TAG_PROPERTIES
+            .iter()
+            .copied()
+            .filter(|name| !record.same_field(&saved, name))
+            .collect()
+    }
+
+    /// Records the current property values as the state on disk
+    pub fn mark_saved(&self) {
+        let imp = self.imp();
+        imp.saved.replace(imp.record.borrow().clone());
+        imp.update_modified();
+    }
+
+    /// Moves the file on disk to `new_path`, creating missing folders, and records the
+    /// move in the undo history and the rename log.
+    pub fn rename_to(&self, new_path: &Path) -> io::Result<()> {
+        let old_path = self.path();
+        if old_path == new_path {
+            return Ok(());
+        }
+        if new_path.exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} already exists", new_path.display()),
+            ));
+        }
+
+        if let Some(parent) = new_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        move_file(&old_path, new_path)?;
+        log_rename(&old_path, new_path);
+        remove_empty_folders(old_path.parent(), new_path);
+
+        self.imp().path.replace(new_path.to_owned());
+        self.notify("path");
+        self.notify("filename");
+
+        if let Some(history) = self.history() {
+            history.record(Change::Rename {
+                taggable_file: self.clone(),
+                old: old_path,
+                new: new_path.to_owned(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Writes the properties that changed since the last save back into the tag of the
+    /// file at `path`. The tag is read again first so that everything else is kept.
+    pub fn save(&self) -> error::Result<()> {
+        let path = self.path();
+        let imp = self.imp();
+        loader::write_record(&path, &imp.record.borrow(), &imp.saved.borrow())?;
+        self.mark_saved();
+        Ok(())
+    }
+}
+
+/// Decodes a picture for display, `None` for image formats GTK can't read
+fn decode_picture(picture: &Picture) -> Option<Texture> {
+    match Texture::from_bytes(&Bytes::from(&picture.data)) {
+        Ok(texture) => Some(texture),
+        Err(err) => {
+            warn!("Can't show a {} picture, {}", picture.mime_type, err);
+            None
+        }
+    }
+}
+
+/// Moves a file, copying it if it has to move to another filesystem
+fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    match fs::rename(from, to) {
+        // io::ErrorKind::CrossesDevices needs a newer Rust than the flatpak SDK has
+        Err(err) if err.raw_os_error() == Some(libc::EXDEV) => {
+            let result = fs::copy(from, to).and_then(|_| fs::remove_file(from));
+            if result.is_err() {
+                // Don't leave a partial or second copy behind
+                let _ = fs::remove_file(to);
             }
+            result
         }
+        result => result,
+    }
+}
+
+/// Removes `folder` and its parents as long as they are empty, stopping at the first
+/// folder that `kept` is in
+fn remove_empty_folders(folder: Option<&Path>, kept: &Path) {
+    let mut folder = folder;
+    while let Some(current) = folder {
+        if kept.starts_with(current) || fs::remove_dir(current).is_err() {
+            break;
+        }
+        folder = current.parent();
+    }
+}
+
+/// Appends a move to the rename log in the user data folder, so that renames can be
+/// traced back even after the application was closed
+fn log_rename(old_path: &Path, new_path: &Path) {
+    let log_path = glib::user_data_dir()
+        .join(env!("CARGO_PKG_NAME"))
+        .join("renames.log");
+    let result = log_path
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|_| {
+            fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&log_path)
+        })
+        .and_then(|mut log| {
+            writeln!(
+                log,
+                "{}\t{}\t{}",
+                glib::DateTime::now_local()
+                    .and_then(|now| now.format_iso8601())
+                    .map(|now| now.to_string())
+                    .unwrap_or_default(),
+                old_path.display(),
+                new_path.display()
+            )
+        });
+    if let Err(err) = result {
+        warn!("Failed to write rename log {}, {}", log_path.display(), err);
+    }
+}
+
+/// Returns the translated name of a property as shown in the UI
+pub fn property_title(property: &str) -> String {
+    match property {
+        "path" => gettext("Path"),
+        "filename" => gettext("Filename"),
+        "title" => gettext("Title"),
+        "artists" => gettext("Artist"),
+        "album" => gettext("Album"),
+        "album-artists" => gettext("Album Artist"),
+        "composer" => gettext("Composer"),
+        "genre" => gettext("Genre"),
+        "duration" => gettext("Duration"),
+        "file-size" => gettext("File Size"),
+        "format" => gettext("Format"),
+        "codec" => gettext("Codec"),
+        "bitrate" => gettext("Bitrate"),
+        "sample-rate" => gettext("Sample Rate"),
+        "bit-depth" => gettext("Bit Depth"),
+        "channels" => gettext("Channels"),
+        "tag-format" => gettext("Tag Format"),
+        "year" => gettext("Year"),
+        "track" => gettext("Track"),
+        "total-tracks" => gettext("Total Tracks"),
+        "disc" => gettext("Disc"),
+        "total-discs" => gettext("Total Discs"),
+        "cover" => gettext("Cover"),
+        "pictures" => gettext("Pictures"),
+        "extra" => gettext("Other Fields"),
+        _ => property.to_owned(),
+    }
+}
 
-        filetag_obj.build()
+/// Returns the translated name of a picture type as shown in the UI
+pub fn picture_type_title(picture_type: PictureType) -> String {
+    match picture_type {
+        PictureType::Other => gettext("Other"),
+        PictureType::FileIcon => gettext("File Icon"),
+        PictureType::OtherFileIcon => gettext("Other File Icon"),
+        PictureType::FrontCover => gettext("Front Cover"),
+        PictureType::BackCover => gettext("Back Cover"),
+        PictureType::Leaflet => gettext("Leaflet"),
+        PictureType::Media => gettext("Media"),
+        PictureType::LeadArtist => gettext("Lead Artist"),
+        PictureType::Artist => gettext("Artist"),
+        PictureType::Conductor => gettext("Conductor"),
+        PictureType::Band => gettext("Band"),
+        PictureType::Composer => gettext("Composer"),
+        PictureType::Lyricist => gettext("Lyricist"),
+        PictureType::RecordingLocation => gettext("Recording Location"),
+        PictureType::DuringRecording => gettext("During Recording"),
+        PictureType::DuringPerformance => gettext("During Performance"),
+        PictureType::ScreenCapture => gettext("Screen Capture"),
+        PictureType::BrightColoredFish => gettext("Bright Colored Fish"),
+        PictureType::Illustration => gettext("Illustration"),
+        PictureType::BandLogo => gettext("Band Logo"),
+        PictureType::PublisherLogo => gettext("Publisher Logo"),
     }
 }
@@ -0,0 +1,60 @@
+use std::path::Path;
+
+/// Tag formats that are read and written with their own library. ID3v2 tags are also
+/// read from and written to the ID3 chunk of WAV and AIFF files.
+pub enum NativeTag {
+    Id3,
+    Flac,
+    Mp4,
+}
+
+/// The native tag format of the file at `path`, by its extension
+pub fn native_tag(path: &Path) -> Option<NativeTag> {
+    let extension = path.extension()?.to_str()?.to_lowercase();
+    match extension.as_str() {
+        "mp3" | "wav" | "wave" | "aif" | "aiff" | "aifc" => Some(NativeTag::Id3),
+        "flac" => Some(NativeTag::Flac),
+        "m4a" | "m4b" | "m4p" | "m4v" | "mp4" | "isom" => Some(NativeTag::Mp4),
+        _ => None,
+    }
+}
+
+/// Reads the ID3v2 tag of an MP3 file or the ID3 chunk of a WAV or AIFF file
+pub fn read_id3(path: &Path) -> id3::Result<id3::Tag> {
+    match id3_container(path) {
+        Id3Container::Mpeg => id3::Tag::read_from_path(path),
+        Id3Container::Wav => id3::Tag::read_from_wav_path(path),
+        Id3Container::Aiff => id3::Tag::read_from_aiff_path(path),
+    }
+}
+
+/// Writes `tag` in `version` to the file at `path`, see `read_id3`
+pub fn write_id3(tag: &id3::Tag, path: &Path, version: id3::Version) -> id3::Result<()> {
+    match id3_container(path) {
+        Id3Container::Mpeg => tag.write_to_path(path, version),
+        Id3Container::Wav => tag.write_to_wav_path(path, version),
+        Id3Container::Aiff => tag.write_to_aiff_path(path, version),
+    }
+}
+
+/// Where the ID3v2 tag of a file is stored
+enum Id3Container {
+    // At the start of the file
+    Mpeg,
+    // In an "id3 " chunk
+    Wav,
+    // In an "ID3 " chunk
+    Aiff,
+}
+
+fn id3_container(path: &Path) -> Id3Container {
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_lowercase());
+    match extension.as_deref() {
+        Some("wav" | "wave") => Id3Container::Wav,
+        Some("aif" | "aiff" | "aifc") => Id3Container::Aiff,
+        _ => Id3Container::Mpeg,
+    }
+}
@@ -0,0 +1,360 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+/// Placeholders usable in patterns and the TaggableFile property they stand for
+pub const PLACEHOLDERS: &[(&str, &str)] = &[
+    ("title", "title"),
+    ("artist", "artists"),
+    ("album", "album"),
+    ("albumartist", "album-artists"),
+    ("year", "year"),
+    ("track", "track"),
+    ("totaltracks", "total-tracks"),
+    ("disc", "disc"),
+    ("totaldiscs", "total-discs"),
+    ("genre", "genre"),
+    ("composer", "composer"),
+];
+
+#[derive(Clone, Debug, PartialEq)]
+enum Segment {
+    Text(String),
+    // Name of the TaggableFile property, `None` for `%dummy%` which matches anything,
+    // and the width numbers are padded to with zeros, e.g. 2 for `%track:02%`
+    Field(Option<&'static str>, Option<usize>),
+}
+
+fn placeholder_property(name: &str) -> Option<&'static str> {
+    PLACEHOLDERS
+        .iter()
+        .find(|(placeholder, _)| *placeholder == name)
+        .map(|(_, property)| *property)
+}
+
+/// Splits one path component of a pattern into text and placeholders.
+/// Returns `None` if the pattern contains an unknown or unterminated placeholder.
+fn segments(pattern: &str) -> Option<Vec<Segment>> {
+    let mut segments = vec![];
+    let mut rest = pattern;
+    while let Some(start) = rest.find('%') {
+        if start > 0 {
+            segments.push(Segment::Text(rest[..start].to_owned()));
+        }
+        let end = rest[start + 1..].find('%')? + start + 1;
+        let (name, width) = match rest[start + 1..end].split_once(':') {
+            Some((name, width)) => (name, Some(width.parse().ok()?)),
+            None => (&rest[start + 1..end], None),
+        };
+        segments.push(match name {
+            "dummy" => Segment::Field(None, width),
+            _ => Segment::Field(Some(placeholder_property(name)?), width),
+        });
+        rest = &rest[end + 1..];
+    }
+    if !rest.is_empty() {
+        segments.push(Segment::Text(rest.to_owned()));
+    }
+    Some(segments)
+}
+
+/// Matches `text` against `segments`, preferring the shortest value for every field
+fn match_segments(segments: &[Segment], text: &str) -> Option<Vec<(&'static str, String)>> {
+    // Adjacent placeholders try every split of the text. Remembering the splits that
+    // failed keeps that from taking exponential time.
+    match_from(segments, text, 0, 0, &mut HashSet::new())
+}
+
+/// Matches `text[offset..]` against `segments[index..]`, see `match_segments`. `failed`
+/// holds the indices and offsets that are known not to match.
+fn match_from(
+    segments: &[Segment],
+    text: &str,
+    index: usize,
+    offset: usize,
+    failed: &mut HashSet<(usize, usize)>,
+) -> Option<Vec<(&'static str, String)>> {
+    if failed.contains(&(index, offset)) {
+        return None;
+    }
+    let rest = &text[offset..];
+    let fields = match segments.get(index) {
+        None => rest.is_empty().then(Vec::new),
+        Some(Segment::Text(literal)) if rest.starts_with(literal.as_str()) => {
+            match_from(segments, text, index + 1, offset + literal.len(), failed)
+        }
+        Some(Segment::Text(_)) => None,
+        Some(Segment::Field(property, _)) if index + 1 == segments.len() => {
+            field_value(*property, rest)
+        }
+        Some(Segment::Field(property, _)) => {
+            let mut ends = rest
+                .char_indices()
+                .skip(1)
+                .map(|(end, _)| offset + end)
+                .collect::<Vec<usize>>();
+            // Numbers take all their digits, so that `%track%%title%` splits "07Intro"
+            // into 7 and "Intro"
+            if property.is_some_and(is_numeric) {
+                ends.reverse();
+            }
+            ends.into_iter().find_map(|end| {
+                let mut fields = field_value(*property, &text[offset..end])?;
+                fields.extend(match_from(segments, text, index + 1, end, failed)?);
+                Some(fields)
+            })
+        }
+    };
+    if fields.is_none() {
+        failed.insert((index, offset));
+    }
+    fields
+}
+
+fn field_value(property: Option<&'static str>, value: &str) -> Option<Vec<(&'static str, String)>> {
+    let value = value.trim();
+    let property = match property {
+        Some(property) => property,
+        None => return Some(vec![]),
+    };
+    if value.is_empty() {
+        return None;
+    }
+
+    if is_numeric(property) {
+        // Drop leading zeros of track and disc numbers
+        let number = value.parse::<u32>().ok()?;
+        Some(vec![(property, number.to_string())])
+    } else {
+        Some(vec![(property, value.to_owned())])
+    }
+}
+
+fn is_numeric(property: &str) -> bool {
+    matches!(
+        property,
+        "year" | "track" | "total-tracks" | "disc" | "total-discs"
+    )
+}
+
+/// Derives tag values from `path` using `pattern`, e.g. `%artist%/%album%/%track% - %title%`.
+/// Every `/` separated part of the pattern is matched against one of the last path
+/// components, the file extension is ignored. Returns `None` if the path does not match.
+pub fn parse_path(pattern: &str, path: &Path) -> Option<Vec<(&'static str, String)>> {
+    let parts = pattern
+        .trim_matches('/')
+        .split('/')
+        .map(segments)
+        .collect::<Option<Vec<Vec<Segment>>>>()?;
+
+    let stem = path.file_stem()?.to_string_lossy().into_owned();
+    let mut components = path
+        .parent()?
+        .iter()
+        .rev()
+        .take(parts.len() - 1)
+        .map(|component| component.to_string_lossy().into_owned())
+        .collect::<Vec<String>>();
+    if components.len() + 1 < parts.len() {
+        return None;
+    }
+    components.reverse();
+    components.push(stem);
+
+    let mut fields = vec![];
+    for (part, component) in parts.iter().zip(components.iter()) {
+        fields.extend(match_segments(part, component)?);
+    }
+    Some(fields)
+}
+
+/// Replaces characters that are not allowed in file names on common filesystems
+pub fn sanitize_component(component: &str) -> String {
+    let sanitized = component
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect::<String>();
+    // Windows drops trailing dots and spaces, which would make names collide
+    let sanitized = sanitized.trim().trim_end_matches('.').trim_end();
+    match sanitized {
+        "" | "." | ".." => String::from("_"),
+        sanitized => sanitized.to_owned(),
+    }
+}
+
+/// Builds a relative path from `template`, e.g. `%albumartist%/%year% - %album%/%track:02% %title%`,
+/// taking the placeholder values from `value`. Every part is sanitized, except for `..`
+/// which moves up one folder. Returns `None` if the template is invalid.
+pub fn format_path<F: Fn(&str) -> String>(
+    template: &str,
+    value: F,
+    extension: Option<&str>,
+) -> Option<PathBuf> {
+    let mut components = vec![];
+    for part in template.trim_matches('/').split('/') {
+        if part == ".." {
+            components.push(String::from(part));
+            continue;
+        }
+
+        let mut component = String::new();
+        for segment in segments(part)? {
+            match segment {
+                Segment::Text(text) => component.push_str(&text),
+                Segment::Field(None, _) => (),
+                Segment::Field(Some(property), width) => {
+                    let value = value(property);
+                    match (width, value.parse::<u32>()) {
+                        (Some(width), Ok(number)) => {
+                            component.push_str(&format!("{:0width$}", number, width = width))
+                        }
+                        _ => component.push_str(&value),
+                    }
+                }
+            }
+        }
+        components.push(sanitize_component(&component));
+    }
+
+    // Appended instead of using set_extension, which would cut titles like "Vol. 2"
+    if let (Some(extension), Some(filename)) = (extension, components.last_mut()) {
+        filename.push('.');
+        filename.push_str(extension);
+    }
+    Some(components.iter().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(pattern: &str, path: &str) -> Option<Vec<(&'static str, String)>> {
+        parse_path(pattern, Path::new(path))
+    }
+
+    fn format(template: &str, extension: Option<&str>) -> Option<PathBuf> {
+        format_path(
+            template,
+            |property| match property {
+                "title" => String::from("AC/DC: Live?"),
+                "artists" => String::from("Artist"),
+                "album" => String::from(".."),
+                "track" => String::from("7"),
+                "year" => String::new(),
+                _ => String::from("x"),
+            },
+            extension,
+        )
+    }
+
+    #[test]
+    fn parses_folders_and_filename() {
+        assert_eq!(
+            parse(
+                "%artist%/%album%/%track% - %title%",
+                "/music/Artist/Album/01 - Title - Part 2.mp3"
+            ),
+            Some(vec![
+                ("artists", String::from("Artist")),
+                ("album", String::from("Album")),
+                ("track", String::from("1")),
+                ("title", String::from("Title - Part 2")),
+            ])
+        );
+    }
+
+    #[test]
+    fn parses_adjacent_placeholders() {
+        assert_eq!(
+            parse("%track%%title%", "07Intro.flac"),
+            Some(vec![
+                ("track", String::from("7")),
+                ("title", String::from("Intro")),
+            ])
+        );
+        assert_eq!(
+            parse("%artist%%title%", "ab.flac"),
+            Some(vec![
+                ("artists", String::from("a")),
+                ("title", String::from("b")),
+            ])
+        );
+    }
+
+    #[test]
+    fn gives_up_on_adjacent_placeholders_in_time() {
+        // Trying every split of 60 characters into 13 fields would not finish
+        let pattern = format!("{}%title% - x", "%dummy%".repeat(12));
+        let path = format!("{}.mp3", "a".repeat(60));
+        assert_eq!(parse(&pattern, &path), None);
+    }
+
+    #[test]
+    fn parses_padded_numbers_and_dummies() {
+        assert_eq!(
+            parse("%track:02% %dummy% - %title%", "03 ignored - Song.ogg"),
+            Some(vec![
+                ("track", String::from("3")),
+                ("title", String::from("Song")),
+            ])
+        );
+    }
+
+    #[test]
+    fn rejects_mismatches_and_invalid_patterns() {
+        assert_eq!(parse("%track% - %title%", "Song.mp3"), None);
+        assert_eq!(parse("%track% - %title%", "A - Song.mp3"), None);
+        assert_eq!(parse("%year% - %title%", " - Song.mp3"), None);
+        assert_eq!(
+            parse("%artist%/%album%/%genre%/%title%", "Album/Song.mp3"),
+            None
+        );
+        assert_eq!(parse("%unknown%", "Song.mp3"), None);
+        assert_eq!(parse("%title", "Song.mp3"), None);
+        assert_eq!(parse("%track:x%", "1.mp3"), None);
+    }
+
+    #[test]
+    fn formats_padded_numbers() {
+        assert_eq!(
+            format("%track:02% %artist%", Some("mp3")),
+            Some(PathBuf::from("07 Artist.mp3"))
+        );
+        assert_eq!(format("%track:3%", None), Some(PathBuf::from("007")));
+    }
+
+    #[test]
+    fn sanitizes_separators_inside_values() {
+        assert_eq!(
+            format("%artist%/%title%", Some("flac")),
+            Some(PathBuf::from("Artist/AC_DC_ Live_.flac"))
+        );
+    }
+
+    #[test]
+    fn keeps_parent_folders_of_the_template_only() {
+        assert_eq!(
+            format("../%album%/%year%", None),
+            Some(PathBuf::from("../_/_"))
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_templates() {
+        assert_eq!(format("%nope%", None), None);
+        assert_eq!(format("%title", None), None);
+    }
+
+    #[test]
+    fn sanitizes_components() {
+        assert_eq!(sanitize_component("a/b\\c:d"), "a_b_c_d");
+        assert_eq!(sanitize_component("tab\there"), "tab_here");
+        assert_eq!(sanitize_component("Vol. 2. "), "Vol. 2");
+        assert_eq!(sanitize_component("..."), "_");
+        assert_eq!(sanitize_component("  "), "_");
+        assert_eq!(sanitize_component(".."), "_");
+    }
+}
@@ -0,0 +1,283 @@
+use crate::taggablefile::error::{Result, TagError};
+use crate::taggablefile::native::{native_tag, read_id3, NativeTag};
+use crate::taggablefile::record::{image_mime_type, Picture, PictureType};
+use id3::TagLike;
+use lofty::{ParseOptions, Probe, TagType, TaggedFileExt};
+use log::debug;
+use mp4ameta::{Img, ImgFmt};
+use std::path::Path;
+
+/// ID3v2 picture types by their number, see `PictureType::number`
+const ID3_PICTURE_TYPES: [id3::frame::PictureType; 21] = {
+    use id3::frame::PictureType::*;
+    [
+        Other,
+        Icon,
+        OtherIcon,
+        CoverFront,
+        CoverBack,
+        Leaflet,
+        Media,
+        LeadArtist,
+        Artist,
+        Conductor,
+        Band,
+        Composer,
+        Lyricist,
+        RecordingLocation,
+        DuringRecording,
+        DuringPerformance,
+        ScreenCapture,
+        BrightFish,
+        Illustration,
+        BandLogo,
+        PublisherLogo,
+    ]
+};
+
+/// FLAC picture types by their number, which are the same as in ID3v2
+const FLAC_PICTURE_TYPES: [metaflac::block::PictureType; 21] = {
+    use metaflac::block::PictureType::*;
+    [
+        Other,
+        Icon,
+        OtherIcon,
+        CoverFront,
+        CoverBack,
+        Leaflet,
+        Media,
+        LeadArtist,
+        Artist,
+        Conductor,
+        Band,
+        Composer,
+        Lyricist,
+        RecordingLocation,
+        DuringRecording,
+        DuringPerformance,
+        ScreenCapture,
+        BrightFish,
+        Illustration,
+        BandLogo,
+        PublisherLogo,
+    ]
+};
+
+/// Looks up the type of a picture read by one of the tag libraries
+fn picture_type<T: PartialEq>(types: &[T], picture_type: &T) -> PictureType {
+    types
+        .iter()
+        .position(|candidate| candidate == picture_type)
+        .map_or(PictureType::Other, |number| {
+            PictureType::from_number(number as u8)
+        })
+}
+
+/// Builds an ID3v2 APIC frame
+fn id3_picture_frame(picture: &Picture) -> id3::Frame {
+    id3::Frame::from(id3::frame::Picture {
+        mime_type: picture.mime_type.clone(),
+        picture_type: ID3_PICTURE_TYPES[usize::from(picture.picture_type.number())],
+        description: picture.description.clone(),
+        data: picture.data.clone(),
+    })
+}
+
+/// Reads the ID3v2 tag of the file at `path`. The id3 crate only keeps the last picture
+/// of each type, so the pictures are read again with lofty, which keeps all of them.
+/// Tags read this way can be written without losing pictures.
+pub fn read_id3_tag(path: &Path) -> id3::Result<id3::Tag> {
+    let mut tag = read_id3(path)?;
+    if tag.pictures().next().is_none() {
+        return Ok(tag);
+    }
+    let tagged_file = Probe::open(path).and_then(|probe| {
+        probe
+            .options(ParseOptions::new().read_properties(false))
+            .read()
+    });
+    // Without a tag from lofty, the pictures the id3 crate read are kept
+    match tagged_file
+        .as_ref()
+        .map(|tagged_file| tagged_file.tag(TagType::Id3v2))
+    {
+        Ok(Some(lofty_tag)) => {
+            let pictures = lofty_tag.pictures();
+            tag.remove_all_pictures();
+            tag.extend(pictures.iter().map(|picture| {
+                id3_picture_frame(&Picture {
+                    picture_type: PictureType::from_number(picture.pic_type().as_u8()),
+                    mime_type: picture.mime_type().as_str().to_owned(),
+                    description: picture.description().unwrap_or_default().to_owned(),
+                    data: picture.data().to_vec(),
+                })
+            }));
+        }
+        Ok(None) => debug!("No ID3v2 tag in {:?} for lofty", path),
+        Err(err) => debug!("Failed to read the pictures of {:?}, {}", path, err),
+    }
+    Ok(tag)
+}
+
+/// The MP4 image format of a picture, MP4 only stores JPEG, PNG and BMP
+fn mp4_image_format(picture: &Picture) -> Option<ImgFmt> {
+    match image_mime_type(&picture.data) {
+        Some("image/jpeg") => Some(ImgFmt::Jpeg),
+        Some("image/png") => Some(ImgFmt::Png),
+        Some("image/bmp") => Some(ImgFmt::Bmp),
+        _ => None,
+    }
+}
+
+/// Fails if one of `pictures` can't be stored in the file at `path`, so that it can be
+/// checked before anything is written
+pub fn check_pictures(path: &Path, pictures: &[Picture]) -> Result<()> {
+    if let Some(NativeTag::Mp4) = native_tag(path) {
+        if let Some(picture) = pictures
+            .iter()
+            .find(|picture| mp4_image_format(picture).is_none())
+        {
+            return Err(TagError::UnsupportedPicture(picture.mime_type.clone()));
+        }
+    }
+    Ok(())
+}
+
+/// Reads all pictures with their type and description. MP4 files have no picture types,
+/// their first picture is taken as front cover.
+pub fn read_pictures(path: &Path) -> Result<Vec<Picture>> {
+    let pictures = match native_tag(path) {
+        Some(NativeTag::Id3) => match read_id3_tag(path) {
+            Ok(tag) => tag
+                .pictures()
+                .map(|picture| Picture {
+                    picture_type: picture_type(&ID3_PICTURE_TYPES, &picture.picture_type),
+                    mime_type: picture.mime_type.clone(),
+                    description: picture.description.clone(),
+                    data: picture.data.clone(),
+                })
+                .collect(),
+            Err(err) if matches!(err.kind, id3::ErrorKind::NoTag) => vec![],
+            Err(err) => return Err(err.into()),
+        },
+        Some(NativeTag::Flac) => metaflac::Tag::read_from_path(path)?
+            .pictures()
+            .map(|picture| Picture {
+                picture_type: picture_type(&FLAC_PICTURE_TYPES, &picture.picture_type),
+                mime_type: picture.mime_type.clone(),
+                description: picture.description.clone(),
+                data: picture.data.clone(),
+            })
+            .collect(),
+        Some(NativeTag::Mp4) => mp4ameta::Tag::read_from_path(path)?
+            .artworks()
+            .enumerate()
+            .map(|(index, artwork)| Picture {
+                picture_type: if index == 0 {
+                    PictureType::FrontCover
+                } else {
+                    PictureType::Other
+                },
+                mime_type: String::from(match artwork.fmt {
+                    ImgFmt::Bmp => "image/bmp",
+                    ImgFmt::Jpeg => "image/jpeg",
+                    ImgFmt::Png => "image/png",
+                }),
+                description: String::new(),
+                data: artwork.data.to_vec(),
+            })
+            .collect(),
+        None => vec![],
+    };
+    Ok(pictures)
+}
+
+/// Replaces all pictures of an ID3v2 tag with `pictures`
+pub fn set_id3_pictures(tag: &mut id3::Tag, pictures: &[Picture]) {
+    tag.remove_all_pictures();
+    // `add_frame` would replace earlier pictures of the same type
+    tag.extend(pictures.iter().map(id3_picture_frame));
+}
+
+/// Replaces all picture blocks of a FLAC tag with `pictures`. Pictures that are written
+/// again keep the dimensions stored with them.
+pub fn set_flac_pictures(tag: &mut metaflac::Tag, pictures: &[Picture]) {
+    let blocks = tag.pictures().cloned().collect::<Vec<_>>();
+    tag.remove_blocks(metaflac::BlockType::Picture);
+    for picture in pictures {
+        let mut block = blocks
+            .iter()
+            .find(|block| block.data == picture.data)
+            .cloned()
+            .unwrap_or_else(metaflac::block::Picture::new);
+        block.picture_type = FLAC_PICTURE_TYPES[usize::from(picture.picture_type.number())];
+        block.mime_type = picture.mime_type.clone();
+        block.description = picture.description.clone();
+        block.data = picture.data.clone();
+        tag.push_block(metaflac::Block::Picture(block));
+    }
+}
+
+/// Replaces all artworks of an MP4 tag with `pictures`. MP4 keeps neither picture types
+/// nor descriptions, the front cover is stored first. Pictures `check_pictures` rejects
+/// are left out.
+pub fn set_mp4_pictures(tag: &mut mp4ameta::Tag, pictures: &[Picture]) {
+    tag.remove_artworks();
+    let front_covers = pictures
+        .iter()
+        .filter(|picture| picture.picture_type == PictureType::FrontCover);
+    let others = pictures
+        .iter()
+        .filter(|picture| picture.picture_type != PictureType::FrontCover);
+    for picture in front_covers.chain(others) {
+        if let Some(fmt) = mp4_image_format(picture) {
+            tag.add_artwork(Img {
+                fmt,
+                data: picture.data.clone(),
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs, process};
+
+    fn picture(picture_type: PictureType, description: &str, data: &[u8]) -> Picture {
+        Picture {
+            picture_type,
+            mime_type: String::from("image/png"),
+            description: description.to_owned(),
+            data: data.to_vec(),
+        }
+    }
+
+    #[test]
+    fn keeps_id3_pictures_of_the_same_type() {
+        // MPEG-1 layer III frames without a tag
+        let mut frame = vec![0xff, 0xfb, 0x90, 0x00];
+        frame.resize(417, 0);
+        let path = env::temp_dir().join(format!("easier-tag-{}-pictures.mp3", process::id()));
+        fs::write(&path, frame.repeat(4)).unwrap();
+
+        let pictures = vec![
+            picture(PictureType::FrontCover, "", b"\x89PNG front"),
+            picture(PictureType::FrontCover, "", b"\x89PNG other front"),
+            picture(PictureType::Artist, "first", b"\x89PNG artist"),
+            picture(PictureType::Artist, "second", b"\x89PNG artist"),
+        ];
+        let mut tag = id3::Tag::new();
+        set_id3_pictures(&mut tag, &pictures);
+        tag.write_to_path(&path, id3::Version::Id3v24).unwrap();
+        let read = read_pictures(&path);
+        // Tags written again by other parts of the tag keep all pictures too
+        let rewritten = read_id3_tag(&path)
+            .and_then(|tag| tag.write_to_path(&path, id3::Version::Id3v23))
+            .map_err(TagError::from)
+            .and_then(|_| read_pictures(&path));
+        fs::remove_file(&path).unwrap();
+        assert_eq!(read.unwrap(), pictures);
+        assert_eq!(rewritten.unwrap(), pictures);
+    }
+}
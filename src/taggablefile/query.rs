@@ -0,0 +1,301 @@
+use crate::taggablefile::pattern::PLACEHOLDERS;
+use crate::taggablefile::record::TagRecord;
+
+/// Properties free text is searched in
+const SEARCHED_PROPERTIES: &[&str] = &[
+    "title",
+    "artists",
+    "album",
+    "album-artists",
+    "composer",
+    "genre",
+    "year",
+];
+
+#[derive(Clone, Debug, PartialEq)]
+enum Term {
+    // Text contained in the filename or any searched property
+    Text(String),
+    // Text contained in one property, an empty text matches files without the field
+    Contains(&'static str, String),
+    Compare(&'static str, Comparison, i64),
+    Missing(&'static str),
+    Has(&'static str),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Comparison {
+    Less,
+    LessOrEqual,
+    Equal,
+    GreaterOrEqual,
+    Greater,
+}
+
+/// A search over the files of the file list. Space separated terms must all match:
+///
+/// - `bach` matches files with "bach" in the filename or any text field
+/// - `artist:bach` matches files with "bach" in the artist, `artist:` files without artist
+/// - `year:<1990` compares numbers with `<`, `<=`, `=`, `>=` or `>`
+/// - `missing:cover` and `has:cover` match files without or with a field
+///
+/// Field names are the placeholders of filename patterns plus `filename` and `cover`.
+/// Values with spaces can be quoted: `album:"the wall"`. Texts are compared
+/// case-insensitively.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Query {
+    terms: Vec<Term>,
+}
+
+impl Query {
+    pub fn parse(text: &str) -> Self {
+        let terms = split_terms(text)
+            .into_iter()
+            .map(|term| parse_term(&term))
+            .collect();
+        Self { terms }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.terms.is_empty()
+    }
+
+    pub fn matches(&self, record: &TagRecord, filename: &str) -> bool {
+        self.terms
+            .iter()
+            .all(|term| term_matches(term, record, filename))
+    }
+}
+
+/// Splits at spaces outside of double quotes, removing the quotes
+fn split_terms(text: &str) -> Vec<String> {
+    let mut terms = vec![];
+    let mut term = String::new();
+    let mut quoted = false;
+    for c in text.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if !term.is_empty() {
+                    terms.push(std::mem::take(&mut term));
+                }
+            }
+            c => term.push(c),
+        }
+    }
+    if !term.is_empty() {
+        terms.push(term);
+    }
+    terms
+}
+
+fn field_property(name: &str) -> Option<&'static str> {
+    match name {
+        "filename" => Some("filename"),
+        "cover" => Some("cover"),
+        _ => PLACEHOLDERS
+            .iter()
+            .find(|(placeholder, _)| *placeholder == name)
+            .map(|(_, property)| *property),
+    }
+}
+
+fn is_numeric(property: &str) -> bool {
+    matches!(
+        property,
+        "year" | "track" | "total-tracks" | "disc" | "total-discs"
+    )
+}
+
+fn parse_term(term: &str) -> Term {
+    let (name, value) = match term.split_once(':') {
+        Some((name, value)) => (name.to_lowercase(), value.trim()),
+        None => return Term::Text(term.to_lowercase()),
+    };
+
+    match name.as_str() {
+        "missing" | "has" => {
+            return match field_property(&value.to_lowercase()) {
+                Some(property) if name == "missing" => Term::Missing(property),
+                Some(property) => Term::Has(property),
+                None => Term::Text(term.to_lowercase()),
+            }
+        }
+        _ => (),
+    }
+
+    let property = match field_property(&name) {
+        Some(property) => property,
+        // Not a field, e.g. a title containing a colon
+        None => return Term::Text(term.to_lowercase()),
+    };
+    if is_numeric(property) {
+        if let Some((comparison, number)) = parse_comparison(value) {
+            return Term::Compare(property, comparison, number);
+        }
+    }
+    Term::Contains(property, value.to_lowercase())
+}
+
+fn parse_comparison(value: &str) -> Option<(Comparison, i64)> {
+    let (comparison, number) = [
+        ("<=", Comparison::LessOrEqual),
+        (">=", Comparison::GreaterOrEqual),
+        ("<", Comparison::Less),
+        (">", Comparison::Greater),
+        ("=", Comparison::Equal),
+    ]
+    .iter()
+    .find_map(|(operator, comparison)| {
+        value
+            .strip_prefix(operator)
+            .map(|number| (*comparison, number))
+    })
+    .unwrap_or((Comparison::Equal, value));
+    number
+        .trim()
+        .parse()
+        .ok()
+        .map(|number| (comparison, number))
+}
+
+fn field_text(record: &TagRecord, filename: &str, property: &str) -> Option<String> {
+    match property {
+        "filename" => Some(filename.to_owned()),
+        _ => record.text(property),
+    }
+}
+
+fn term_matches(term: &Term, record: &TagRecord, filename: &str) -> bool {
+    match term {
+        Term::Text(text) => {
+            filename.to_lowercase().contains(text)
+                || SEARCHED_PROPERTIES.iter().any(|property| {
+                    record
+                        .text(property)
+                        .is_some_and(|value| value.to_lowercase().contains(text))
+                })
+        }
+        Term::Contains(property, text) if text.is_empty() => {
+            field_text(record, filename, property).is_none()
+        }
+        Term::Contains(property, text) => field_text(record, filename, property)
+            .is_some_and(|value| value.to_lowercase().contains(text)),
+        Term::Compare(property, comparison, number) => record
+            .text(property)
+            .and_then(|value| value.parse::<i64>().ok())
+            .is_some_and(|value| match comparison {
+                Comparison::Less => value < *number,
+                Comparison::LessOrEqual => value <= *number,
+                Comparison::Equal => value == *number,
+                Comparison::GreaterOrEqual => value >= *number,
+                Comparison::Greater => value > *number,
+            }),
+        Term::Missing(property) => field_text(record, filename, property).is_none(),
+        Term::Has(property) => field_text(record, filename, property).is_some(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::taggablefile::record::{Picture, PictureType};
+
+    fn record() -> TagRecord {
+        TagRecord {
+            title: Some(String::from("Another Brick in the Wall")),
+            artists: vec![String::from("Pink Floyd")],
+            album: Some(String::from("The Wall")),
+            year: Some(1979),
+            track: Some(5),
+            ..Default::default()
+        }
+    }
+
+    fn matches(query: &str) -> bool {
+        Query::parse(query).matches(&record(), "05 Another Brick.flac")
+    }
+
+    #[test]
+    fn parses_terms() {
+        assert!(Query::parse("  ").is_empty());
+        assert_eq!(
+            Query::parse(r#"Bach album:"the wall" year:<=1990 MISSING:Cover has:genre"#).terms,
+            vec![
+                Term::Text(String::from("bach")),
+                Term::Contains("album", String::from("the wall")),
+                Term::Compare("year", Comparison::LessOrEqual, 1990),
+                Term::Missing("cover"),
+                Term::Has("genre"),
+            ]
+        );
+        // Unknown fields and numbers that aren't comparisons are searched as text
+        assert_eq!(
+            Query::parse("intro:outro year:70s missing:mood").terms,
+            vec![
+                Term::Text(String::from("intro:outro")),
+                Term::Contains("year", String::from("70s")),
+                Term::Text(String::from("missing:mood")),
+            ]
+        );
+    }
+
+    #[test]
+    fn matches_text_in_fields_and_filename() {
+        assert!(matches("floyd"));
+        assert!(matches("WALL brick"));
+        assert!(matches(".flac"));
+        assert!(matches("1979"));
+        assert!(!matches("floyd zeppelin"));
+    }
+
+    #[test]
+    fn matches_quoted_terms() {
+        assert!(matches(r#""the wall""#));
+        assert!(matches(r#"album:"the wall""#));
+        assert!(!matches(r#"album:"wall the""#));
+        assert!(matches(r#"title:"brick in""#));
+        assert!(matches(r#"filename:"05 another""#));
+    }
+
+    #[test]
+    fn matches_fields() {
+        assert!(matches("artist:pink"));
+        assert!(!matches("albumartist:pink"));
+        // An empty value matches files without the field
+        assert!(matches("genre:"));
+        assert!(!matches("album:"));
+    }
+
+    #[test]
+    fn compares_numbers() {
+        assert!(matches("year:<1990"));
+        assert!(!matches("year:<1979"));
+        assert!(matches("year:<=1979"));
+        assert!(matches("year:1979"));
+        assert!(matches("year:=1979"));
+        assert!(matches("track:>4"));
+        assert!(!matches("track:>=6"));
+        // Files without the field never match a comparison
+        assert!(!matches("disc:<100"));
+    }
+
+    #[test]
+    fn matches_missing_and_present_fields() {
+        assert!(matches("missing:cover"));
+        assert!(matches("missing:genre"));
+        assert!(!matches("missing:album"));
+        assert!(matches("has:year has:filename"));
+        assert!(!matches("has:cover"));
+
+        let mut record = record();
+        record.pictures.push(Picture {
+            picture_type: PictureType::BackCover,
+            mime_type: String::from("image/png"),
+            description: String::new(),
+            data: vec![0x89, b'P', b'N', b'G'],
+        });
+        assert!(Query::parse("has:cover").matches(&record, "a.flac"));
+        assert!(!Query::parse("missing:cover").matches(&record, "a.flac"));
+    }
+}
@@ -0,0 +1,510 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// Purpose of a picture stored in a tag, numbered like the ID3v2 picture types
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum PictureType {
+    Other,
+    FileIcon,
+    OtherFileIcon,
+    FrontCover,
+    BackCover,
+    Leaflet,
+    Media,
+    LeadArtist,
+    Artist,
+    Conductor,
+    Band,
+    Composer,
+    Lyricist,
+    RecordingLocation,
+    DuringRecording,
+    DuringPerformance,
+    ScreenCapture,
+    BrightColoredFish,
+    Illustration,
+    BandLogo,
+    PublisherLogo,
+}
+
+impl PictureType {
+    pub const ALL: [PictureType; 21] = [
+        PictureType::Other,
+        PictureType::FileIcon,
+        PictureType::OtherFileIcon,
+        PictureType::FrontCover,
+        PictureType::BackCover,
+        PictureType::Leaflet,
+        PictureType::Media,
+        PictureType::LeadArtist,
+        PictureType::Artist,
+        PictureType::Conductor,
+        PictureType::Band,
+        PictureType::Composer,
+        PictureType::Lyricist,
+        PictureType::RecordingLocation,
+        PictureType::DuringRecording,
+        PictureType::DuringPerformance,
+        PictureType::ScreenCapture,
+        PictureType::BrightColoredFish,
+        PictureType::Illustration,
+        PictureType::BandLogo,
+        PictureType::PublisherLogo,
+    ];
+
+    /// The ID3v2 / FLAC number of the picture type
+    pub fn number(self) -> u8 {
+        self as u8
+    }
+
+    pub fn from_number(number: u8) -> Self {
+        Self::ALL
+            .get(number as usize)
+            .copied()
+            .unwrap_or(PictureType::Other)
+    }
+}
+
+/// An image stored in a tag
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Picture {
+    pub picture_type: PictureType,
+    pub mime_type: String,
+    pub description: String,
+    pub data: Vec<u8>,
+}
+
+impl Picture {
+    /// Creates a picture from the contents of an image file, `None` if they are not in
+    /// one of the image formats tags usually contain
+    pub fn from_image_data(picture_type: PictureType, data: Vec<u8>) -> Option<Self> {
+        Some(Self {
+            picture_type,
+            mime_type: String::from(image_mime_type(&data)?),
+            description: String::new(),
+            data,
+        })
+    }
+}
+
+/// Recognizes the image format of `data` by its first bytes
+pub fn image_mime_type(data: &[u8]) -> Option<&'static str> {
+    match data {
+        [0xff, 0xd8, 0xff, ..] => Some("image/jpeg"),
+        [0x89, b'P', b'N', b'G', ..] => Some("image/png"),
+        [b'G', b'I', b'F', b'8', ..] => Some("image/gif"),
+        [b'B', b'M', ..] => Some("image/bmp"),
+        [b'I', b'I', 0x2a, 0x00, ..] | [b'M', b'M', 0x00, 0x2a, ..] => Some("image/tiff"),
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => Some("image/webp"),
+        _ => None,
+    }
+}
+
+/// Properties of the audio file itself rather than of its tag. They are read along with
+/// the tag and never written.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FileProperties {
+    pub duration: Option<Duration>,
+    pub file_size: Option<u64>,
+    // Container format, e.g. "FLAC" or "MP4"
+    pub format: Option<String>,
+    // Audio codec, e.g. "FLAC" or "AAC"
+    pub codec: Option<String>,
+    // Average bitrate in kbit/s
+    pub bitrate: Option<u32>,
+    // In Hz
+    pub sample_rate: Option<u32>,
+    // Only known for lossless codecs
+    pub bit_depth: Option<u8>,
+    pub channels: Option<u8>,
+    // The tag formats found in the file, e.g. "ID3v2.4, ID3v1"
+    pub tag_format: Option<String>,
+}
+
+/// The tag of an audio file with typed values, independent of GTK and of the tag
+/// format. Missing fields are `None` or empty.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TagRecord {
+    pub title: Option<String>,
+    pub artists: Vec<String>,
+    pub album: Option<String>,
+    pub album_artists: Vec<String>,
+    pub composer: Option<String>,
+    pub genre: Option<String>,
+    pub year: Option<i32>,
+    pub track: Option<u16>,
+    pub total_tracks: Option<u16>,
+    pub disc: Option<u16>,
+    pub total_discs: Option<u16>,
+    pub file: FileProperties,
+    pub pictures: Vec<Picture>,
+    // Fields without a typed counterpart by their key in the tag format, e.g. an ID3v2
+    // frame id or a Vorbis comment name. They are kept as they are when writing.
+    pub extra: BTreeMap<String, Vec<String>>,
+}
+
+/// A text that can't be stored in a numeric field
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InvalidValue {
+    pub property: String,
+    pub text: String,
+}
+
+impl std::fmt::Display for InvalidValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} is not a valid {}", self.text, self.property)
+    }
+}
+
+impl std::error::Error for InvalidValue {}
+
+impl TagRecord {
+    /// Returns a field as shown in the UI, lists joined with ", ". `property` is the
+    /// name of the corresponding TaggableFile property.
+    pub fn text(&self, property: &str) -> Option<String> {
+        let list = |list: &Vec<String>| Some(list.join(", ")).filter(|text| !text.is_empty());
+        match property {
+            "title" => self.title.clone(),
+            "artists" => list(&self.artists),
+            "album" => self.album.clone(),
+            "album-artists" => list(&self.album_artists),
+            "composer" => self.composer.clone(),
+            "genre" => self.genre.clone(),
+            "year" => self.year.map(|year| year.to_string()),
+            "track" => self.track.map(|track| track.to_string()),
+            "total-tracks" => self.total_tracks.map(|total| total.to_string()),
+            "disc" => self.disc.map(|disc| disc.to_string()),
+            "total-discs" => self.total_discs.map(|total| total.to_string()),
+            "duration" => self.file.duration.map(|duration| {
+                let seconds = duration.as_secs();
+                format!("{}:{:02}", seconds / 60, seconds % 60)
+            }),
+            "file-size" => self.file.file_size.map(format_size),
+            "format" => self.file.format.clone(),
+            "codec" => self.file.codec.clone(),
+            "bitrate" => self
+                .file
+                .bitrate
+                .map(|bitrate| format!("{} kbit/s", bitrate)),
+            "sample-rate" => self
+                .file
+                .sample_rate
+                .map(|sample_rate| format!("{} kHz", f64::from(sample_rate) / 1000.0)),
+            "bit-depth" => self
+                .file
+                .bit_depth
+                .map(|bit_depth| format!("{} bit", bit_depth)),
+            "channels" => self.file.channels.map(|channels| channels.to_string()),
+            "tag-format" => self.file.tag_format.clone(),
+            "cover" => self
+                .front_cover()
+                .map(|cover| format!("{}, {} bytes", cover.mime_type, cover.data.len())),
+            _ => None,
+        }
+    }
+
+    /// Sets a field from its text as entered in the UI, lists being separated by commas.
+    /// An empty text removes the field.
+    pub fn set_text(&mut self, property: &str, text: &str) -> Result<(), InvalidValue> {
+        let text = text.trim();
+        let value = Some(text.to_owned()).filter(|text| !text.is_empty());
+        let invalid = || InvalidValue {
+            property: property.to_owned(),
+            text: text.to_owned(),
+        };
+        if !is_valid_value(property, text) {
+            return Err(invalid());
+        }
+
+        match property {
+            "title" => self.title = value,
+            "artists" => self.artists = split_list(text),
+            "album" => self.album = value,
+            "album-artists" => self.album_artists = split_list(text),
+            "composer" => self.composer = value,
+            "genre" => self.genre = value,
+            "year" => self.year = parse_number(text).map_err(|_| invalid())?,
+            "track" => self.track = parse_number(text).map_err(|_| invalid())?,
+            "total-tracks" => self.total_tracks = parse_number(text).map_err(|_| invalid())?,
+            "disc" => self.disc = parse_number(text).map_err(|_| invalid())?,
+            "total-discs" => self.total_discs = parse_number(text).map_err(|_| invalid())?,
+            _ => return Err(invalid()),
+        }
+        Ok(())
+    }
+
+    /// Removes a field
+    pub fn clear(&mut self, property: &str) {
+        match property {
+            "artists" => self.artists.clear(),
+            "album-artists" => self.album_artists.clear(),
+            "cover" => self.set_front_cover(None),
+            "pictures" => self.pictures.clear(),
+            "extra" => self.extra.clear(),
+            _ => {
+                // Never fails, the empty text removes every field
+                let _ = self.set_text(property, "");
+            }
+        }
+    }
+
+    /// Sets a field to its value in `other`
+    pub fn copy_field(&mut self, other: &TagRecord, property: &str) {
+        match property {
+            "title" => self.title = other.title.clone(),
+            "artists" => self.artists = other.artists.clone(),
+            "album" => self.album = other.album.clone(),
+            "album-artists" => self.album_artists = other.album_artists.clone(),
+            "composer" => self.composer = other.composer.clone(),
+            "genre" => self.genre = other.genre.clone(),
+            "year" => self.year = other.year,
+            "track" => self.track = other.track,
+            "total-tracks" => self.total_tracks = other.total_tracks,
+            "disc" => self.disc = other.disc,
+            "total-discs" => self.total_discs = other.total_discs,
+            "cover" => self.set_front_cover(other.front_cover().cloned()),
+            "pictures" => self.pictures = other.pictures.clone(),
+            "extra" => self.extra = other.extra.clone(),
+            _ => (),
+        }
+    }
+
+    /// Whether a field has the same value in both records
+    pub fn same_field(&self, other: &TagRecord, property: &str) -> bool {
+        match property {
+            "artists" => self.artists == other.artists,
+            "album-artists" => self.album_artists == other.album_artists,
+            "cover" => self.front_cover() == other.front_cover(),
+            "pictures" => self.pictures == other.pictures,
+            "extra" => self.extra == other.extra,
+            _ => self.text(property) == other.text(property),
+        }
+    }
+
+    /// The front cover, or the first picture if there is no picture marked as front cover
+    pub fn front_cover(&self) -> Option<&Picture> {
+        self.pictures
+            .iter()
+            .find(|picture| picture.picture_type == PictureType::FrontCover)
+            .or_else(|| self.pictures.first())
+    }
+
+    /// Replaces the front cover, `None` removes it
+    pub fn set_front_cover(&mut self, cover: Option<Picture>) {
+        let position = self
+            .pictures
+            .iter()
+            .position(|picture| picture.picture_type == PictureType::FrontCover)
+            .or(if self.pictures.is_empty() {
+                None
+            } else {
+                Some(0)
+            });
+        match (position, cover) {
+            (Some(position), Some(cover)) => self.pictures[position] = cover,
+            (Some(position), None) => {
+                self.pictures.remove(position);
+            }
+            (None, Some(cover)) => self.pictures.insert(0, cover),
+            (None, None) => (),
+        }
+    }
+}
+
+/// Formats a number of bytes with decimal units like file managers do, e.g. "4.2 MB"
+fn format_size(bytes: u64) -> String {
+    const UNITS: &[&str] = &["kB", "MB", "GB", "TB"];
+    if bytes < 1000 {
+        return format!("{} bytes", bytes);
+    }
+    let mut size = bytes as f64 / 1000.0;
+    let mut unit = 0;
+    while size >= 1000.0 && unit < UNITS.len() - 1 {
+        size /= 1000.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+fn parse_number<T: std::str::FromStr>(text: &str) -> Result<Option<T>, T::Err> {
+    if text.is_empty() {
+        return Ok(None);
+    }
+    text.parse().map(Some)
+}
+
+/// Checks whether `text` can be stored in the tag property `property`.
+/// Empty values are always valid and remove the field on save.
+pub fn is_valid_value(property: &str, text: &str) -> bool {
+    let text = text.trim();
+    if text.is_empty() {
+        return true;
+    }
+    match property {
+        // Most tag formats only store years from 0 on
+        "year" => matches!(text.parse::<i32>(), Ok(year) if year >= 0),
+        "disc" | "total-discs" | "track" | "total-tracks" => text.parse::<u16>().is_ok(),
+        _ => true,
+    }
+}
+
+/// Splits a comma separated list as shown in the UI into its entries
+pub fn split_list(text: &str) -> Vec<String> {
+    text.split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn picture(picture_type: PictureType, data: &[u8]) -> Picture {
+        Picture {
+            picture_type,
+            mime_type: String::from("image/png"),
+            description: String::new(),
+            data: data.to_vec(),
+        }
+    }
+
+    #[test]
+    fn empty_text_removes_fields() {
+        let mut record = TagRecord {
+            title: Some(String::from("Title")),
+            artists: vec![String::from("A"), String::from("B")],
+            track: Some(3),
+            ..Default::default()
+        };
+        record.set_text("title", "  ").unwrap();
+        record.set_text("artists", "").unwrap();
+        record.set_text("track", "").unwrap();
+        assert_eq!(record, TagRecord::default());
+        assert_eq!(record.text("artists"), None);
+    }
+
+    #[test]
+    fn sets_and_rejects_numbers() {
+        let mut record = TagRecord::default();
+        record.set_text("track", " 12 ").unwrap();
+        record.set_text("year", "0500").unwrap();
+        assert_eq!(record.track, Some(12));
+        assert_eq!(record.year, Some(500));
+
+        assert_eq!(
+            record.set_text("track", "65536"),
+            Err(InvalidValue {
+                property: String::from("track"),
+                text: String::from("65536"),
+            })
+        );
+        assert!(record.set_text("disc", "-1").is_err());
+        assert!(record.set_text("year", "1990s").is_err());
+        assert!(record.set_text("year", "-500").is_err());
+        assert!(record.set_text("duration", "1:00").is_err());
+        // Failed edits keep the old value
+        assert_eq!(record.track, Some(12));
+    }
+
+    #[test]
+    fn splits_lists() {
+        assert_eq!(
+            split_list(" A ,B,, ,C "),
+            vec![String::from("A"), String::from("B"), String::from("C")]
+        );
+        assert!(split_list(" , ").is_empty());
+
+        let mut record = TagRecord::default();
+        record.set_text("album-artists", "X, Y").unwrap();
+        assert_eq!(record.text("album-artists").as_deref(), Some("X, Y"));
+    }
+
+    #[test]
+    fn validates_values() {
+        assert!(is_valid_value("track", ""));
+        assert!(is_valid_value("track", " 65535 "));
+        assert!(!is_valid_value("track", "65536"));
+        assert!(!is_valid_value("total-discs", "two"));
+        assert!(!is_valid_value("year", "-1"));
+        assert!(is_valid_value("year", "0"));
+        assert!(!is_valid_value("year", "1.5"));
+        assert!(is_valid_value("title", "anything"));
+    }
+
+    #[test]
+    fn formats_sizes() {
+        assert_eq!(format_size(0), "0 bytes");
+        assert_eq!(format_size(999), "999 bytes");
+        assert_eq!(format_size(1000), "1.0 kB");
+        assert_eq!(format_size(4_200_000), "4.2 MB");
+        assert_eq!(format_size(3_000_000_000_000_000), "3000.0 TB");
+    }
+
+    #[test]
+    fn front_cover_falls_back_to_first_picture() {
+        let mut record = TagRecord {
+            pictures: vec![
+                picture(PictureType::Artist, b"artist"),
+                picture(PictureType::Media, b"media"),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(record.front_cover(), Some(&record.pictures[0]));
+
+        // Without a front cover the first picture is the one that is replaced
+        let cover = picture(PictureType::FrontCover, b"cover");
+        record.set_front_cover(Some(cover.clone()));
+        assert_eq!(
+            record.pictures,
+            vec![cover, picture(PictureType::Media, b"media")]
+        );
+
+        // Removing falls back to the first picture as well
+        record.set_front_cover(None);
+        record.set_front_cover(None);
+        assert!(record.pictures.is_empty());
+        record.set_front_cover(None);
+        assert!(record.pictures.is_empty());
+    }
+
+    #[test]
+    fn replaces_marked_front_cover() {
+        let mut record = TagRecord {
+            pictures: vec![
+                picture(PictureType::BackCover, b"back"),
+                picture(PictureType::FrontCover, b"front"),
+            ],
+            ..Default::default()
+        };
+        let cover = picture(PictureType::FrontCover, b"new");
+        record.set_front_cover(Some(cover.clone()));
+        assert_eq!(
+            record.pictures,
+            vec![picture(PictureType::BackCover, b"back"), cover]
+        );
+    }
+
+    #[test]
+    fn compares_cover_and_pictures_separately() {
+        let front = picture(PictureType::FrontCover, b"front");
+        let record = TagRecord {
+            pictures: vec![front.clone(), picture(PictureType::BackCover, b"back")],
+            ..Default::default()
+        };
+        let other = TagRecord {
+            pictures: vec![front],
+            ..Default::default()
+        };
+        assert!(record.same_field(&other, "cover"));
+        assert!(!record.same_field(&other, "pictures"));
+
+        let mut copy = TagRecord::default();
+        copy.copy_field(&record, "cover");
+        assert!(copy.same_field(&record, "cover"));
+        assert!(!copy.same_field(&record, "pictures"));
+        copy.copy_field(&record, "pictures");
+        assert!(copy.same_field(&record, "pictures"));
+    }
+}
@@ -0,0 +1,84 @@
+use crate::taggablefile::record::TagRecord;
+use std::cmp::Ordering;
+
+/// Compares texts the way people expect, with numbers by their value: "Track 2" sorts
+/// before "Track 10". Letters are compared case-insensitively.
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut a_chunks = chunks(a);
+    let mut b_chunks = chunks(b);
+    loop {
+        match (a_chunks.next(), b_chunks.next()) {
+            // Only differences in case or leading zeros are left
+            (None, None) => return a.cmp(b),
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(a_chunk), Some(b_chunk)) => match compare_chunks(a_chunk, b_chunk) {
+                Ordering::Equal => continue,
+                ordering => return ordering,
+            },
+        }
+    }
+}
+
+/// Splits `text` into runs of digits and runs of other characters
+fn chunks(text: &str) -> impl Iterator<Item = &str> {
+    let mut rest = text;
+    std::iter::from_fn(move || {
+        let is_digit = rest.chars().next()?.is_ascii_digit();
+        let end = rest
+            .find(|c: char| c.is_ascii_digit() != is_digit)
+            .unwrap_or(rest.len());
+        let (chunk, tail) = rest.split_at(end);
+        rest = tail;
+        Some(chunk)
+    })
+}
+
+fn compare_chunks(a: &str, b: &str) -> Ordering {
+    if a.starts_with(|c: char| c.is_ascii_digit()) && b.starts_with(|c: char| c.is_ascii_digit()) {
+        let a = a.trim_start_matches('0');
+        let b = b.trim_start_matches('0');
+        a.len().cmp(&b.len()).then_with(|| a.cmp(b))
+    } else {
+        a.to_lowercase().cmp(&b.to_lowercase())
+    }
+}
+
+/// Missing values sort after all others
+fn compare_options<T: Ord>(a: Option<T>, b: Option<T>) -> Ordering {
+    match (a, b) {
+        (Some(a), Some(b)) => a.cmp(&b),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    }
+}
+
+/// Compares two tags by the field of the TaggableFile property `property`. Numbers are
+/// compared by value, texts naturally. Albums are ordered by disc and track within.
+pub fn compare_field(a: &TagRecord, b: &TagRecord, property: &str) -> Ordering {
+    match property {
+        "year" => compare_options(a.year, b.year),
+        "disc" => compare_options(a.disc, b.disc),
+        "total-discs" => compare_options(a.total_discs, b.total_discs),
+        "track" => compare_options(a.track, b.track),
+        "total-tracks" => compare_options(a.total_tracks, b.total_tracks),
+        "duration" => compare_options(a.file.duration, b.file.duration),
+        "file-size" => compare_options(a.file.file_size, b.file.file_size),
+        "bitrate" => compare_options(a.file.bitrate, b.file.bitrate),
+        "sample-rate" => compare_options(a.file.sample_rate, b.file.sample_rate),
+        "bit-depth" => compare_options(a.file.bit_depth, b.file.bit_depth),
+        "channels" => compare_options(a.file.channels, b.file.channels),
+        "album" => compare_text(a, b, property)
+            .then_with(|| compare_field(a, b, "disc"))
+            .then_with(|| compare_field(a, b, "track")),
+        _ => compare_text(a, b, property),
+    }
+}
+
+fn compare_text(a: &TagRecord, b: &TagRecord, property: &str) -> Ordering {
+    match (a.text(property), b.text(property)) {
+        (Some(a), Some(b)) => natural_cmp(&a, &b),
+        (a, b) => compare_options(a.map(|_| ()), b.map(|_| ())),
+    }
+}
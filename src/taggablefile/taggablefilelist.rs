@@ -1,25 +1,86 @@
+use crate::taggablefile::error::TagError;
+use crate::taggablefile::history::TagHistory;
+use crate::taggablefile::loader::TagLoader;
 use crate::taggablefile::TaggableFile;
-use audiotags::{Tag, TagType};
-use core::cell::RefCell;
+use core::cell::{Cell, RefCell};
 use gtk::{
-    builders::DirectoryListBuilder, gio, gio::File, glib, prelude::*, subclass::prelude::*,
+    builders::DirectoryListBuilder,
+    gio,
+    gio::File,
+    glib,
+    glib::{
+        clone, ParamSpec, ParamSpecBoolean, ParamSpecDouble, ParamSpecUInt, SignalHandlerId, Value,
+    },
+    prelude::*,
+    subclass::prelude::*,
     DirectoryList,
 };
+use log::{debug, warn};
+use once_cell::sync::Lazy;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+
+/// A folder whose files are shown in the model
+#[derive(Debug)]
+pub struct WatchedFolder {
+    pub directory_list: DirectoryList,
+    // Path of every item of `directory_list`, `None` for items that are not files
+    pub paths: Vec<Option<PathBuf>>,
+    pub handlers: Vec<SignalHandlerId>,
+}
 
 mod imp {
     use super::*;
 
     #[derive(Debug)]
     pub struct TaggableFileListModel {
-        pub directory_lists: RefCell<Vec<DirectoryList>>,
+        // Folders that were added, without the subfolders found when scanning recursively
+        pub folders: RefCell<Vec<File>>,
+        // Files that were added on their own, e.g. from the command line
+        pub files: RefCell<Vec<PathBuf>>,
+        pub watched_folders: RefCell<Vec<WatchedFolder>>,
         pub taggable_files: RefCell<Vec<TaggableFile>>,
+        // Position of every file in `taggable_files` by its path. Rebuilt when it is
+        // dirty, i.e. after a file was renamed.
+        pub positions: RefCell<HashMap<PathBuf, usize>>,
+        pub positions_dirty: Cell<bool>,
+        pub history: TagHistory,
+
+        pub recursive: Cell<bool>,
+        pub max_depth: Cell<u32>,
+        // Counts the subfolder scans, so that results of a replaced scan are ignored
+        pub scan: Cell<u32>,
+        pub scanning: Cell<bool>,
+
+        pub loaders: RefCell<Vec<TagLoader>>,
+        // Files whose tags are being read
+        pub pending: RefCell<HashSet<PathBuf>>,
+        // Number of files queued for loading since the model was last idle and how many
+        // of them are done
+        pub files_to_load: Cell<u32>,
+        pub files_loaded: Cell<u32>,
     }
 
     impl Default for TaggableFileListModel {
         fn default() -> Self {
             Self {
-                directory_lists: RefCell::new(vec![]),
+                folders: RefCell::new(vec![]),
+                files: RefCell::new(vec![]),
+                watched_folders: RefCell::new(vec![]),
                 taggable_files: RefCell::new(vec![]),
+                positions: RefCell::new(HashMap::new()),
+                positions_dirty: Cell::new(false),
+                history: TagHistory::new(),
+                recursive: Cell::new(false),
+                max_depth: Cell::new(8),
+                scan: Cell::new(0),
+                scanning: Cell::new(false),
+                loaders: RefCell::new(vec![]),
+                pending: RefCell::new(HashSet::new()),
+                files_to_load: Cell::new(0),
+                files_loaded: Cell::new(0),
             }
         }
     }
@@ -31,7 +92,52 @@ mod imp {
         type Interfaces = (gio::ListModel,);
     }
 
-    impl ObjectImpl for TaggableFileListModel {}
+    impl ObjectImpl for TaggableFileListModel {
+        fn properties() -> &'static [ParamSpec] {
+            static PROPERTIES: Lazy<Vec<ParamSpec>> = Lazy::new(|| {
+                vec![
+                    ParamSpecBoolean::builder("recursive").build(),
+                    ParamSpecUInt::builder("max-depth").build(),
+                    ParamSpecBoolean::builder("loading").read_only().build(),
+                    ParamSpecDouble::builder("progress")
+                        .minimum(0.0)
+                        .maximum(1.0)
+                        .read_only()
+                        .build(),
+                ]
+            });
+            PROPERTIES.as_ref()
+        }
+
+        fn set_property(&self, _id: usize, value: &Value, pspec: &ParamSpec) {
+            match pspec.name() {
+                "recursive" => self.recursive.set(value.get().unwrap()),
+                "max-depth" => self.max_depth.set(value.get().unwrap()),
+                _ => unimplemented!(),
+            }
+            // Pick up or drop the files in subfolders
+            self.obj().reload();
+        }
+
+        fn property(&self, _id: usize, pspec: &ParamSpec) -> Value {
+            match pspec.name() {
+                "recursive" => self.recursive.get().to_value(),
+                "max-depth" => self.max_depth.get().to_value(),
+                "loading" => self.is_loading().to_value(),
+                "progress" => match self.files_to_load.get() {
+                    0 => 1.0.to_value(),
+                    files_to_load => {
+                        (self.files_loaded.get() as f64 / files_to_load as f64).to_value()
+                    }
+                },
+                _ => unimplemented!(),
+            }
+        }
+
+        fn dispose(&self) {
+            self.cancel_loading();
+        }
+    }
 
     impl ListModelImpl for TaggableFileListModel {
         fn item_type(&self) -> glib::Type {
@@ -49,55 +155,357 @@ mod imp {
     }
 
     impl TaggableFileListModel {
-        pub fn rebuild_taglist(&self) {
-            for list in self.directory_lists.borrow().iter() {
-                for i in 0..list.n_items() {
-                    if let Some(item) = list.item(i) {
-                        if let Some(filetag) = filetag_from_directorylist(&list, i) {
-                            self.taggable_files.borrow_mut().push(filetag);
-                        }
-                    }
+        /// Shows the files of `folder` and keeps them in sync with the folder on disk
+        pub fn watch(&self, folder: &File) {
+            let obj = self.obj();
+            let directory_list = DirectoryListBuilder::new()
+                .attributes("standard::*")
+                .file(folder)
+                .monitored(true)
+                .build();
+
+            let handlers = vec![
+                directory_list.connect_items_changed(
+                    clone!(@weak obj => move |directory_list, position, removed, added| {
+                        obj.imp().directory_list_changed(directory_list, position, removed, added);
+                    }),
+                ),
+                directory_list.connect_loading_notify(clone!(@weak obj => move |_| {
+                    obj.imp().notify_progress();
+                })),
+            ];
+            let n_items = directory_list.n_items();
+            self.watched_folders.borrow_mut().push(WatchedFolder {
+                directory_list: directory_list.clone(),
+                paths: vec![],
+                handlers,
+            });
+
+            if n_items > 0 {
+                self.directory_list_changed(&directory_list, 0, 0, n_items);
+            }
+            self.notify_progress();
+        }
+
+        /// Stops showing the files of the watched folder at `index`
+        pub fn unwatch(&self, index: usize) {
+            let folder = self.watched_folders.borrow_mut().remove(index);
+            for handler in folder.handlers {
+                folder.directory_list.disconnect(handler);
+            }
+            for path in folder.paths.into_iter().flatten() {
+                // Files that were also added on their own stay, as do unsaved changes
+                if !self.files.borrow().contains(&path) && !self.is_modified(&path) {
+                    self.remove_file(&path);
+                }
+            }
+            self.notify_progress();
+        }
+
+        /// Applies a change of a DirectoryList to the files in this model. Files that are
+        /// removed and added again in the same change were changed on disk.
+        fn directory_list_changed(
+            &self,
+            directory_list: &DirectoryList,
+            position: u32,
+            removed: u32,
+            added: u32,
+        ) {
+            let added_paths = (position..position + added)
+                .map(|position| file_path(directory_list, position))
+                .collect::<Vec<Option<PathBuf>>>();
+
+            let removed_paths = {
+                let mut watched_folders = self.watched_folders.borrow_mut();
+                let folder = match watched_folders
+                    .iter_mut()
+                    .find(|folder| &folder.directory_list == directory_list)
+                {
+                    Some(folder) => folder,
+                    None => return,
+                };
+                let start = (position as usize).min(folder.paths.len());
+                let end = (start + removed as usize).min(folder.paths.len());
+                folder
+                    .paths
+                    .splice(start..end, added_paths.iter().cloned())
+                    .flatten()
+                    .collect::<Vec<PathBuf>>()
+            };
+
+            let mut paths_to_load = vec![];
+            for path in removed_paths.iter() {
+                if added_paths.iter().flatten().any(|added| added == path) {
+                    self.file_changed(path, &mut paths_to_load);
+                } else {
+                    self.remove_file(path);
+                }
+            }
+            for path in added_paths.into_iter().flatten() {
+                if !removed_paths.contains(&path)
+                    && self.position(&path).is_none()
+                    && !self.pending.borrow().contains(&path)
+                {
+                    paths_to_load.push(path);
+                }
+            }
+            self.load(paths_to_load);
+        }
+
+        /// Reads the tags of a file again after it changed on disk, unless the user
+        /// edited them in the meantime
+        fn file_changed(&self, path: &Path, paths_to_load: &mut Vec<PathBuf>) {
+            if self.is_modified(path) {
+                warn!(
+                    "{} changed on disk, keeping the unsaved changes",
+                    path.display()
+                );
+            } else {
+                paths_to_load.push(path.to_owned());
+            }
+        }
+
+        pub fn load(&self, paths: Vec<PathBuf>) {
+            if paths.is_empty() {
+                return;
+            }
+
+            let obj = self.obj();
+            self.pending.borrow_mut().extend(paths.iter().cloned());
+            self.files_to_load
+                .set(self.files_to_load.get() + paths.len() as u32);
+
+            // Reversed as the workers take paths from the end
+            let paths = paths.into_iter().rev().collect();
+            let loader = TagLoader::start(
+                paths,
+                clone!(@weak obj => move |path, taggable_file| {
+                    obj.imp().file_loaded(path, taggable_file);
+                }),
+            );
+            self.loaders.borrow_mut().push(loader);
+            self.notify_progress();
+        }
+
+        fn file_loaded(&self, path: PathBuf, taggable_file: Option<TaggableFile>) {
+            // The file was removed while its tags were read
+            if !self.pending.borrow_mut().remove(&path) {
+                return;
+            }
+            self.files_loaded.set(self.files_loaded.get() + 1);
+
+            match (taggable_file, self.position(&path)) {
+                (Some(taggable_file), Some(position)) => {
+                    self.adopt(&taggable_file);
+                    let old = std::mem::replace(
+                        &mut self.taggable_files.borrow_mut()[position],
+                        taggable_file.clone(),
+                    );
+                    // Undo and redo edit the file that is shown now
+                    self.history.replace_file(&old, &taggable_file);
+                    self.obj().items_changed(position as u32, 1, 1);
+                }
+                (Some(taggable_file), None) => {
+                    self.adopt(&taggable_file);
+                    let position = self.taggable_files.borrow().len();
+                    self.positions.borrow_mut().insert(path, position);
+                    self.taggable_files.borrow_mut().push(taggable_file);
+                    self.obj().items_changed(position as u32, 0, 1);
+                }
+                // The file can't be read anymore after it changed on disk
+                (None, Some(position)) => self.remove_at(position),
+                (None, None) => debug!("{} has no readable tag", path.display()),
+            }
+
+            self.finish_loading();
+        }
+
+        /// Records the changes of a file that is added to the model in its history
+        fn adopt(&self, taggable_file: &TaggableFile) {
+            taggable_file.set_history(&self.history);
+            let obj = self.obj();
+            taggable_file.connect_notify_local(
+                Some("path"),
+                clone!(@weak obj => move |_, _| {
+                    obj.imp().positions_dirty.set(true);
+                }),
+            );
+        }
+
+        fn remove_file(&self, path: &Path) {
+            if self.pending.borrow_mut().remove(path) {
+                self.files_loaded.set(self.files_loaded.get() + 1);
+                self.finish_loading();
+            }
+            if let Some(position) = self.position(path) {
+                self.remove_at(position);
+            }
+        }
+
+        fn remove_at(&self, position: usize) {
+            let taggable_file = self.taggable_files.borrow_mut().remove(position);
+            if !self.positions_dirty.get() {
+                let mut positions = self.positions.borrow_mut();
+                positions.remove(&taggable_file.path());
+                for later in positions.values_mut().filter(|later| **later > position) {
+                    *later -= 1;
+                }
+            }
+            self.obj().items_changed(position as u32, 1, 0);
+        }
+
+        /// Resets the progress once all pending files are loaded
+        fn finish_loading(&self) {
+            if self.pending.borrow().is_empty() {
+                self.loaders.borrow_mut().clear();
+                self.files_to_load.set(0);
+                self.files_loaded.set(0);
+            }
+            self.notify_progress();
+        }
+
+        /// Whether the file at `path` is shown with unsaved changes
+        fn is_modified(&self, path: &Path) -> bool {
+            self.position(path)
+                .and_then(|position| self.taggable_files.borrow().get(position).cloned())
+                .is_some_and(|taggable_file| taggable_file.property::<bool>("modified"))
+        }
+
+        pub fn position(&self, path: &Path) -> Option<usize> {
+            if self.positions_dirty.replace(false) {
+                let positions = self
+                    .taggable_files
+                    .borrow()
+                    .iter()
+                    .enumerate()
+                    .map(|(position, taggable_file)| (taggable_file.path(), position))
+                    .collect();
+                self.positions.replace(positions);
+            }
+            self.positions.borrow().get(path).copied()
+        }
+
+        /// Stops reading tags of files that are not needed anymore
+        pub fn cancel_loading(&self) {
+            for loader in self.loaders.take() {
+                loader.cancel();
+            }
+            self.pending.borrow_mut().clear();
+            self.files_to_load.set(0);
+            self.files_loaded.set(0);
+            self.notify_progress();
+        }
+
+        fn is_loading(&self) -> bool {
+            self.scanning.get()
+                || !self.pending.borrow().is_empty()
+                || self
+                    .watched_folders
+                    .borrow()
+                    .iter()
+                    .any(|folder| folder.directory_list.is_loading())
+        }
+
+        pub fn notify_progress(&self) {
+            self.obj().notify("loading");
+            self.obj().notify("progress");
+        }
+
+        /// Watches exactly `folders` and stops watching all others. Files in folders that
+        /// stay watched keep unsaved changes.
+        pub fn watch_only(&self, folders: Vec<File>) {
+            // A folder can be below several added folders, but its files are only shown once
+            let mut unique: Vec<File> = vec![];
+            for folder in folders {
+                if !unique.iter().any(|other| other.equal(&folder)) {
+                    unique.push(folder);
+                }
+            }
+
+            let unwatched = self
+                .watched_folders
+                .borrow()
+                .iter()
+                .enumerate()
+                .filter(|(_, watched)| {
+                    let file = watched.directory_list.file();
+                    !unique
+                        .iter()
+                        .any(|folder| file.as_ref().is_some_and(|file| file.equal(folder)))
+                })
+                .map(|(index, _)| index)
+                .collect::<Vec<usize>>();
+            for index in unwatched.into_iter().rev() {
+                self.unwatch(index);
+            }
+
+            for folder in unique {
+                let watched = self.watched_folders.borrow().iter().any(|watched| {
+                    watched
+                        .directory_list
+                        .file()
+                        .is_some_and(|file| file.equal(&folder))
+                });
+                if !watched {
+                    self.watch(&folder);
                 }
             }
         }
     }
 
-    fn filetag_from_directorylist(
-        directory_list: &DirectoryList,
-        position: u32,
-    ) -> Option<TaggableFile> {
-        if let Some(file) = directory_list.item(position) {
-            if let Ok(fileinfo) = file.downcast::<gio::FileInfo>() {
-                let path = fileinfo.name();
-                if let Ok(tag) = Tag::new().read_from_path(&path) {
-                    return Some(TaggableFile::new(
-                        path.to_str().expect("filepath is not valid utf-8"),
-                        tag.title().unwrap_or(""),
-                        tag.album_title().unwrap_or(""),
-                        tag.composer().unwrap_or(""),
-                        tag.genre().unwrap_or(""),
-                        tag.duration(),
-                        tag.year(),
-                        tag.disc_number(),
-                        tag.total_discs(),
-                        tag.track_number(),
-                        tag.total_tracks(),
-                        tag.artists()
-                            .unwrap_or(vec![])
-                            .into_iter()
-                            .map(|artist| artist.to_owned())
-                            .collect(),
-                        tag.album_artists()
-                            .unwrap_or(vec![])
-                            .into_iter()
-                            .map(|artist| artist.to_owned())
-                            .collect(),
-                        tag.album_cover(),
-                    ));
+    /// Returns the path of the item at `position` of `directory_list` if it is a file
+    fn file_path(directory_list: &DirectoryList, position: u32) -> Option<PathBuf> {
+        let fileinfo = directory_list
+            .item(position)
+            .and_then(|item| item.downcast::<gio::FileInfo>().ok())?;
+        if fileinfo.file_type() == gio::FileType::Directory {
+            return None;
+        }
+        fileinfo
+            .attribute_object("standard::file")
+            .and_then(|file| file.downcast::<gio::File>().ok())
+            .and_then(|file| file.path())
+    }
+
+    /// Returns `folder` and, up to `max_depth` levels deep, all folders below it.
+    /// Every folder is only returned once, so symbolic links pointing to a parent
+    /// folder can't cause an endless scan.
+    pub fn subfolders(folder: &Path, max_depth: u32) -> Vec<PathBuf> {
+        let mut visited = HashSet::new();
+        let mut folders = vec![];
+        let mut pending = vec![(folder.to_owned(), 0)];
+
+        while let Some((folder, depth)) = pending.pop() {
+            match fs::canonicalize(&folder) {
+                Ok(canonical) => {
+                    if !visited.insert(canonical) {
+                        continue;
+                    }
+                }
+                Err(err) => {
+                    warn!("Skipping {}, {}", folder.display(), err);
+                    continue;
+                }
+            }
+
+            if depth < max_depth {
+                match fs::read_dir(&folder) {
+                    Ok(entries) => {
+                        let mut children = entries
+                            .filter_map(|entry| entry.ok())
+                            .map(|entry| entry.path())
+                            .filter(|path| path.is_dir())
+                            .collect::<Vec<PathBuf>>();
+                        // Reversed as the last pending folder is scanned first
+                        children.sort_unstable_by(|a, b| b.cmp(a));
+                        pending.extend(children.into_iter().map(|child| (child, depth + 1)));
+                    }
+                    Err(err) => warn!("Failed to list {}, {}", folder.display(), err),
                 }
             }
+            folders.push(folder);
         }
-        None
+        folders
     }
 }
 
@@ -111,18 +519,132 @@ impl TaggableFileListModel {
         glib::Object::new(&[])
     }
     pub fn add_folder(&self, file: &impl IsA<File>) {
-        let dirlist = DirectoryListBuilder::new()
-            .file(file)
-            .monitored(true)
-            .build();
-        //dirlist.connect_items_changed(|_,_,_,_| self.imp().rebuild_taglist());
-        self.imp().directory_lists.borrow_mut().push(dirlist);
+        self.imp().folders.borrow_mut().push(file.as_ref().clone());
+        self.reload();
     }
-    pub fn rebuild_taglist(&self) {
-        self.imp().rebuild_taglist();
+    /// Adds a single file without the other files in its folder
+    pub fn add_file(&self, file: &impl IsA<File>) {
+        let imp = self.imp();
+        let path = match file.as_ref().path() {
+            Some(path) => path,
+            None => {
+                warn!("Not adding {}, it is not a local file", file.as_ref().uri());
+                return;
+            }
+        };
+        if imp.files.borrow().contains(&path) {
+            return;
+        }
+
+        imp.files.borrow_mut().push(path.clone());
+        if imp.position(&path).is_none() && !imp.pending.borrow().contains(&path) {
+            imp.load(vec![path]);
+        }
+    }
+    /// Brings the watched folders in line with the added folders, e.g. after switching
+    /// recursive scanning on. Files that are no longer in a watched folder are removed,
+    /// unless they have unsaved changes.
+    pub fn reload(&self) {
+        let imp = self.imp();
+        let folders = imp.folders.borrow().clone();
+        let scan = imp.scan.get().wrapping_add(1);
+        imp.scan.set(scan);
+        if !imp.recursive.get() {
+            imp.scanning.set(false);
+            imp.watch_only(folders);
+            return;
+        }
+
+        // Listing the subfolders of a large or remote folder takes a while, so it is done
+        // on a worker thread. Folders that are not local are watched without subfolders.
+        let roots = folders
+            .iter()
+            .map(|folder| folder.path())
+            .collect::<Vec<Option<PathBuf>>>();
+        let max_depth = imp.max_depth.get();
+        let (sender, receiver) = glib::MainContext::channel(glib::PRIORITY_DEFAULT_IDLE);
+        thread::spawn(move || {
+            let scanned = roots
+                .iter()
+                .map(|root| root.as_ref().map(|root| imp::subfolders(root, max_depth)))
+                .collect::<Vec<Option<Vec<PathBuf>>>>();
+            // Fails if the model was disposed in the meantime
+            let _ = sender.send(scanned);
+        });
+        imp.scanning.set(true);
+        imp.notify_progress();
+
+        receiver.attach(
+            None,
+            clone!(@weak self as model => @default-return glib::Continue(false),
+                move |scanned: Vec<Option<Vec<PathBuf>>>| {
+                    let imp = model.imp();
+                    // Another reload started a newer scan
+                    if imp.scan.get() != scan {
+                        return glib::Continue(false);
+                    }
+                    imp.scanning.set(false);
+                    let mut to_watch = vec![];
+                    for (folder, subfolders) in folders.iter().zip(scanned) {
+                        match subfolders {
+                            Some(subfolders) => {
+                                to_watch.extend(subfolders.iter().map(File::for_path))
+                            }
+                            None => to_watch.push(folder.clone()),
+                        }
+                    }
+                    imp.watch_only(to_watch);
+                    imp.notify_progress();
+                    glib::Continue(false)
+                }
+            ),
+        );
     }
     pub fn clear_folders(&self) {
-        self.imp().directory_lists.borrow_mut().clear();
+        let imp = self.imp();
+        imp.cancel_loading();
+        // Results of a running subfolder scan are not needed anymore
+        imp.scan.set(imp.scan.get().wrapping_add(1));
+        imp.scanning.set(false);
+        imp.folders.borrow_mut().clear();
+        imp.files.borrow_mut().clear();
+        for folder in imp.watched_folders.take() {
+            for handler in folder.handlers {
+                folder.directory_list.disconnect(handler);
+            }
+        }
+
+        imp.positions.borrow_mut().clear();
+        imp.positions_dirty.set(false);
+        let removed = imp.taggable_files.take().len() as u32;
+        self.items_changed(0, removed, 0);
+        imp.history.clear();
+        imp.notify_progress();
+    }
+    /// Undo history of the tag changes made to the files in this model
+    pub fn history(&self) -> TagHistory {
+        self.imp().history.clone()
+    }
+    /// Returns the files whose tags differ from what is on disk
+    pub fn modified_files(&self) -> Vec<TaggableFile> {
+        self.imp()
+            .taggable_files
+            .borrow()
+            .iter()
+            .filter(|taggable_file| taggable_file.property::<bool>("modified"))
+            .cloned()
+            .collect()
+    }
+    pub fn has_unsaved_changes(&self) -> bool {
+        !self.modified_files().is_empty()
+    }
+    /// Saves the tags of all modified files, returning the files that could not be written
+    /// together with the reason.
+    pub fn save(&self) -> Vec<(TaggableFile, TagError)> {
+        self.modified_files()
+            .into_iter()
+            .filter_map(|taggable_file| taggable_file.save().err().map(|err| (taggable_file, err)))
+            .collect()
     }
 }
 
@@ -0,0 +1,209 @@
+use crate::config::APP_ID;
+use crate::taggablefile::pattern::{parse_path, PLACEHOLDERS};
+use crate::taggablefile::{property_title, TaggableFile};
+use gettextrs::gettext;
+use gtk::{
+    gio, glib, glib::clone, prelude::*, subclass::prelude::*, Button, CompositeTemplate, Entry,
+    Grid, Label,
+};
+use std::cell::RefCell;
+
+mod imp {
+    use super::*;
+
+    #[derive(CompositeTemplate)]
+    #[template(resource = "/net/fhannenheim/EasierTag/ui/tagsfromfilename.ui")]
+    pub struct TagsFromFilenameDialog {
+        #[template_child]
+        pub pattern_entry: TemplateChild<Entry>,
+        #[template_child]
+        pub placeholders_label: TemplateChild<Label>,
+        #[template_child]
+        pub preview_grid: TemplateChild<Grid>,
+        #[template_child]
+        pub cancel_button: TemplateChild<Button>,
+        #[template_child]
+        pub apply_button: TemplateChild<Button>,
+
+        pub files: RefCell<Vec<TaggableFile>>,
+        pub settings: gio::Settings,
+    }
+
+    impl Default for TagsFromFilenameDialog {
+        fn default() -> Self {
+            Self {
+                pattern_entry: TemplateChild::default(),
+                placeholders_label: TemplateChild::default(),
+                preview_grid: TemplateChild::default(),
+                cancel_button: TemplateChild::default(),
+                apply_button: TemplateChild::default(),
+                files: RefCell::new(vec![]),
+                settings: gio::Settings::new(APP_ID),
+            }
+        }
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for TagsFromFilenameDialog {
+        // `NAME` needs to match `class` attribute of template
+        const NAME: &'static str = "TagsFromFilenameDialog";
+        type Type = super::TagsFromFilenameDialog;
+        type ParentType = gtk::Window;
+
+        fn class_init(klass: &mut Self::Class) {
+            klass.bind_template();
+        }
+
+        fn instance_init(obj: &glib::subclass::InitializingObject<Self>) {
+            obj.init_template();
+        }
+    }
+
+    impl ObjectImpl for TagsFromFilenameDialog {
+        fn constructed(&self) {
+            self.parent_constructed();
+            let obj = self.obj();
+
+            let placeholders = PLACEHOLDERS
+                .iter()
+                .map(|(placeholder, _)| format!("%{}%", placeholder))
+                .chain(std::iter::once(String::from("%dummy%")))
+                .collect::<Vec<String>>()
+                .join(" ");
+            self.placeholders_label.set_label(&format!(
+                "{} {}",
+                gettext("Available placeholders:"),
+                placeholders
+            ));
+
+            self.pattern_entry
+                .set_text(&self.settings.string("filename-pattern"));
+            self.pattern_entry
+                .connect_changed(clone!(@weak obj => move |_| obj.update_preview()));
+            self.pattern_entry
+                .connect_activate(clone!(@weak obj => move |_| obj.apply()));
+            self.cancel_button
+                .connect_clicked(clone!(@weak obj => move |_| obj.close()));
+            self.apply_button
+                .connect_clicked(clone!(@weak obj => move |_| obj.apply()));
+        }
+    }
+
+    impl WidgetImpl for TagsFromFilenameDialog {}
+    impl WindowImpl for TagsFromFilenameDialog {}
+}
+
+glib::wrapper! {
+    pub struct TagsFromFilenameDialog(ObjectSubclass<imp::TagsFromFilenameDialog>)
+        @extends gtk::Widget, gtk::Window;
+}
+
+impl TagsFromFilenameDialog {
+    pub fn new(parent: &impl IsA<gtk::Window>, files: Vec<TaggableFile>) -> Self {
+        let dialog: Self = glib::Object::builder()
+            .property("transient-for", parent)
+            .build();
+        dialog.imp().files.replace(files);
+        dialog.update_preview();
+        dialog
+    }
+
+    fn pattern(&self) -> String {
+        self.imp().pattern_entry.text().to_string()
+    }
+
+    /// Shows the values every file would get with the current pattern
+    fn update_preview(&self) {
+        let imp = self.imp();
+        let grid = imp.preview_grid.get();
+        while let Some(child) = grid.first_child() {
+            grid.remove(&child);
+        }
+
+        let pattern = self.pattern();
+        let properties = PLACEHOLDERS
+            .iter()
+            .map(|(_, property)| *property)
+            .filter(|property| {
+                PLACEHOLDERS
+                    .iter()
+                    .any(|(placeholder, placeholder_property)| {
+                        placeholder_property == property
+                            && pattern.contains(&format!("%{}%", placeholder))
+                    })
+            })
+            .collect::<Vec<&str>>();
+
+        let header = |column: i32, text: &str| {
+            let label = Label::new(Some(text));
+            label.set_xalign(0.0);
+            label.add_css_class("heading");
+            grid.attach(&label, column, 0, 1, 1);
+        };
+        header(0, &gettext("Filename"));
+        for (column, property) in properties.iter().enumerate() {
+            header(column as i32 + 1, &property_title(property));
+        }
+
+        let mut matched = false;
+        for (row, taggable_file) in imp.files.borrow().iter().enumerate() {
+            let row = row as i32 + 1;
+            let filename = Label::new(Some(taggable_file.property::<String>("filename").as_str()));
+            filename.set_xalign(0.0);
+            grid.attach(&filename, 0, row, 1, 1);
+
+            match parse_path(&pattern, &taggable_file.path()) {
+                Some(fields) => {
+                    matched = true;
+                    for (column, property) in properties.iter().enumerate() {
+                        let value = fields
+                            .iter()
+                            .find(|(field, _)| field == property)
+                            .map(|(_, value)| value.as_str())
+                            .unwrap_or_default();
+                        let label = Label::new(Some(value));
+                        label.set_xalign(0.0);
+                        grid.attach(&label, column as i32 + 1, row, 1, 1);
+                    }
+                }
+                None => {
+                    let label = Label::new(Some(gettext("Does not match the pattern").as_str()));
+                    label.set_xalign(0.0);
+                    label.add_css_class("dim-label");
+                    grid.attach(&label, 1, row, properties.len().max(1) as i32, 1);
+                }
+            }
+        }
+
+        imp.apply_button.set_sensitive(matched);
+    }
+
+    /// Sets the parsed values on all files matching the pattern as one undo step
+    fn apply(&self) {
+        let imp = self.imp();
+        let pattern = self.pattern();
+        let files = imp.files.borrow().clone();
+
+        let apply_pattern = || {
+            for taggable_file in files.iter() {
+                if let Some(fields) = parse_path(&pattern, &taggable_file.path()) {
+                    for (property, value) in fields {
+                        taggable_file.set_property_text(property, &value);
+                    }
+                }
+            }
+        };
+        match files
+            .first()
+            .and_then(|taggable_file| taggable_file.history())
+        {
+            Some(history) => history.group(apply_pattern),
+            None => apply_pattern(),
+        }
+
+        if let Err(err) = imp.settings.set_string("filename-pattern", &pattern) {
+            log::warn!("Failed to save filename pattern, {}", err);
+        }
+        self.close();
+    }
+}
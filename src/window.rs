@@ -1,14 +1,21 @@
+use gettextrs::gettext;
 use gtk::prelude::*;
 use gtk::subclass::prelude::*;
-use gtk::{gio, glib};
+use gtk::{gdk, gio, glib, glib::clone};
 
 use crate::application::EasierTagApplication;
 use crate::config::{APP_ID, PROFILE};
+use crate::coverpanel::CoverPanel;
 use crate::filecolumnview::FileColumnView;
 use crate::folderbrowser::FolderBrowser;
+use crate::renamefiles::RenameFilesDialog;
+use crate::tageditor::TagEditor;
+use crate::taggablefile::TaggableFile;
+use crate::tagsfromfilename::TagsFromFilenameDialog;
 
 mod imp {
     use super::*;
+    use std::cell::Cell;
 
     #[derive(Debug, gtk::CompositeTemplate)]
     #[template(resource = "/net/fhannenheim/EasierTag/ui/window.ui")]
@@ -17,7 +24,13 @@ mod imp {
         pub folderbrowser: TemplateChild<FolderBrowser>,
         #[template_child]
         pub filecolumnview: TemplateChild<FileColumnView>,
+        #[template_child]
+        pub coverpanel: TemplateChild<CoverPanel>,
+        #[template_child]
+        pub tageditor: TemplateChild<TagEditor>,
         pub settings: gio::Settings,
+        // Set once the user chose to close the window without saving
+        pub discard_changes: Cell<bool>,
     }
 
     impl Default for EasierTagApplicationWindow {
@@ -25,7 +38,10 @@ mod imp {
             Self {
                 folderbrowser: TemplateChild::default(),
                 filecolumnview: TemplateChild::default(),
+                coverpanel: TemplateChild::default(),
+                tageditor: TemplateChild::default(),
                 settings: gio::Settings::new(APP_ID),
+                discard_changes: Cell::new(false),
             }
         }
     }
@@ -58,6 +74,8 @@ mod imp {
 
             // Load latest window state
             obj.load_window_size();
+
+            obj.setup_gactions();
         }
     }
 
@@ -65,6 +83,18 @@ mod imp {
     impl WindowImpl for EasierTagApplicationWindow {
         // Save window state on delete event
         fn close_request(&self) -> gtk::Inhibit {
+            let obj = self.instance();
+            if !self.discard_changes.get() && obj.filecolumnview().file_list().has_unsaved_changes()
+            {
+                obj.confirm_unsaved_changes(clone!(@weak obj => move |proceed| {
+                    if proceed {
+                        obj.imp().discard_changes.set(true);
+                        obj.close();
+                    }
+                }));
+                return gtk::Inhibit(true);
+            }
+
             if let Err(err) = self.instance().save_window_size() {
                 log::warn!("Failed to save window state, {}", &err);
             }
@@ -88,6 +118,137 @@ impl EasierTagApplicationWindow {
         glib::Object::new(&[("application", app)])
     }
 
+    fn setup_gactions(&self) {
+        // Choose the folder shown in the folder browser
+        let action_choose_folder = gio::ActionEntry::builder("choose-folder")
+            .activate(|window: &Self, _, _| {
+                window.imp().folderbrowser.choose_folder();
+            })
+            .build();
+
+        // Search the file list
+        let action_search = gio::ActionEntry::builder("search")
+            .activate(|window: &Self, _, _| {
+                window.filecolumnview().toggle_search();
+            })
+            .build();
+
+        // Save
+        let action_save = gio::ActionEntry::builder("save")
+            .activate(|window: &Self, _, _| {
+                window.save();
+            })
+            .build();
+
+        // Undo
+        let action_undo = gio::ActionEntry::builder("undo")
+            .activate(|window: &Self, _, _| {
+                window.filecolumnview().file_list().history().undo();
+            })
+            .build();
+
+        // Redo
+        let action_redo = gio::ActionEntry::builder("redo")
+            .activate(|window: &Self, _, _| {
+                window.filecolumnview().file_list().history().redo();
+            })
+            .build();
+
+        // Tags from filename
+        let action_tags_from_filename = gio::ActionEntry::builder("tags-from-filename")
+            .activate(|window: &Self, _, _| {
+                TagsFromFilenameDialog::new(window, window.target_files()).present();
+            })
+            .build();
+
+        // Rename files
+        let action_rename_files = gio::ActionEntry::builder("rename-files")
+            .activate(|window: &Self, _, _| {
+                RenameFilesDialog::new(window, window.target_files()).present();
+            })
+            .build();
+        self.add_action_entries([
+            action_choose_folder,
+            action_search,
+            action_save,
+            action_undo,
+            action_redo,
+            action_tags_from_filename,
+            action_rename_files,
+        ])
+        .unwrap();
+    }
+
+    /// Writes the tags of all modified files to disk and reports files that failed.
+    /// Returns whether all files were saved.
+    pub fn save(&self) -> bool {
+        let failed = self.filecolumnview().file_list().save();
+        if failed.is_empty() {
+            return true;
+        }
+
+        let details = failed
+            .iter()
+            .map(|(taggable_file, err)| {
+                let path = taggable_file.path();
+                log::warn!("Failed to save tags of {}, {}", path.display(), err);
+                format!("{}: {}", path.display(), err)
+            })
+            .collect::<Vec<String>>()
+            .join("\n");
+
+        let dialog = gtk::MessageDialog::builder()
+            .transient_for(self)
+            .modal(true)
+            .message_type(gtk::MessageType::Error)
+            .buttons(gtk::ButtonsType::Close)
+            .text(&gettext("Some files could not be saved"))
+            .secondary_text(&details)
+            .build();
+        dialog.connect_response(|dialog, _| dialog.close());
+        dialog.present();
+        false
+    }
+
+    /// Asks the user to save or discard unsaved changes before continuing.
+    /// `callback` receives whether the pending operation may proceed; it is called
+    /// immediately if there is nothing to save.
+    pub fn confirm_unsaved_changes<F: Fn(bool) + 'static>(&self, callback: F) {
+        if !self.filecolumnview().file_list().has_unsaved_changes() {
+            callback(true);
+            return;
+        }
+
+        let dialog = gtk::MessageDialog::builder()
+            .transient_for(self)
+            .modal(true)
+            .message_type(gtk::MessageType::Question)
+            .text(&gettext("Save changes to the tags?"))
+            .secondary_text(&gettext(
+                "Some files have unsaved changes. If you don't save, they will be lost.",
+            ))
+            .build();
+        dialog.add_buttons(&[
+            (gettext("_Cancel").as_str(), gtk::ResponseType::Cancel),
+            (gettext("_Discard").as_str(), gtk::ResponseType::Reject),
+            (gettext("_Save").as_str(), gtk::ResponseType::Accept),
+        ]);
+        dialog.set_default_response(gtk::ResponseType::Accept);
+        if let Some(button) = dialog.widget_for_response(gtk::ResponseType::Reject) {
+            button.add_css_class("destructive-action");
+        }
+
+        dialog.connect_response(clone!(@weak self as window => move |dialog, response| {
+            dialog.close();
+            match response {
+                gtk::ResponseType::Accept => callback(window.save()),
+                gtk::ResponseType::Reject => callback(true),
+                _ => callback(false),
+            }
+        }));
+        dialog.present();
+    }
+
     fn save_window_size(&self) -> Result<(), glib::BoolError> {
         let imp = self.imp();
 
@@ -123,5 +284,115 @@ impl EasierTagApplicationWindow {
     pub fn init(&self) {
         self.imp().folderbrowser.init(self);
         self.imp().filecolumnview.init();
+
+        let file_list = self.filecolumnview().file_list();
+        self.imp()
+            .settings
+            .bind("recursive-scan", &file_list, "recursive")
+            .build();
+        self.imp()
+            .settings
+            .bind("scan-depth", &file_list, "max-depth")
+            .build();
+        self.add_action(&self.imp().settings.create_action("recursive-scan"));
+
+        // Only allow undo and redo when there is something to undo or redo
+        let history = self.filecolumnview().file_list().history();
+        for (action_name, property) in [("undo", "can-undo"), ("redo", "can-redo")] {
+            if let Some(action) = self
+                .lookup_action(action_name)
+                .and_then(|action| action.downcast::<gio::SimpleAction>().ok())
+            {
+                history
+                    .bind_property(property, &action, "enabled")
+                    .flags(glib::BindingFlags::SYNC_CREATE)
+                    .build();
+            }
+        }
+
+        // Files dropped onto the file list are added to it, dropped anywhere else they
+        // replace the shown files
+        let window_drop_target =
+            gtk::DropTarget::new(gdk::FileList::static_type(), gdk::DragAction::COPY);
+        window_drop_target.connect_drop(
+            clone!(@weak self as window => @default-return false, move |_, value, _, _| {
+                match value.get::<gdk::FileList>() {
+                    Ok(file_list) => {
+                        window.open_files(&file_list.files());
+                        true
+                    }
+                    Err(_) => false,
+                }
+            }),
+        );
+        self.add_controller(&window_drop_target);
+
+        let file_list_drop_target =
+            gtk::DropTarget::new(gdk::FileList::static_type(), gdk::DragAction::COPY);
+        file_list_drop_target.connect_drop(
+            clone!(@weak self as window => @default-return false, move |_, value, _, _| {
+                match value.get::<gdk::FileList>() {
+                    Ok(file_list) => {
+                        window.add_files(&file_list.files());
+                        true
+                    }
+                    Err(_) => false,
+                }
+            }),
+        );
+        self.filecolumnview().add_controller(&file_list_drop_target);
+
+        let selection_model = self.filecolumnview().selection_model();
+        selection_model.connect_selection_changed(clone!(@weak self as window => move |_, _, _| {
+            window.update_tageditor();
+        }));
+        selection_model.connect_items_changed(clone!(@weak self as window => move |_, _, _, _| {
+            window.update_tageditor();
+        }));
+    }
+
+    /// Shows the tags of `files` instead of the files of the selected folders. Folders
+    /// are shown like folders selected in the folder browser.
+    pub fn open_files(&self, files: &[gio::File]) {
+        let files = files.to_vec();
+        self.confirm_unsaved_changes(clone!(@weak self as window => move |proceed| {
+            if proceed {
+                window.filecolumnview().file_list().clear_folders();
+                window.imp().folderbrowser.clear_selection();
+                window.add_files(&files);
+            }
+        }));
+    }
+
+    /// Adds `files` and the files in folders among them to the shown files
+    pub fn add_files(&self, files: &[gio::File]) {
+        let file_list = self.filecolumnview().file_list();
+        for file in files {
+            match file.query_file_type(gio::FileQueryInfoFlags::NONE, gio::Cancellable::NONE) {
+                gio::FileType::Directory => file_list.add_folder(file),
+                gio::FileType::Regular => file_list.add_file(file),
+                _ => log::warn!(
+                    "Not opening {}, it is not a file or folder",
+                    file.parse_name()
+                ),
+            }
+        }
+    }
+
+    /// Files operations like renaming apply to: the selected files, or all shown files if
+    /// nothing is selected. Files whose tag can't be edited are left out.
+    pub fn target_files(&self) -> Vec<TaggableFile> {
+        let mut files = self.filecolumnview().selected_files();
+        if files.is_empty() {
+            files = self.filecolumnview().shown_files();
+        }
+        files.retain(TaggableFile::is_editable);
+        files
+    }
+
+    fn update_tageditor(&self) {
+        let selected_files = self.filecolumnview().selected_files();
+        self.imp().coverpanel.set_files(selected_files.clone());
+        self.imp().tageditor.set_files(selected_files);
     }
 }